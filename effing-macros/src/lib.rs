@@ -1,72 +1,307 @@
-// we're already on nightly for generators, so we might as well :)
-#![feature(let_else)]
-
 use proc_macro::TokenStream;
 use proc_macro2::Span;
-use quote::{format_ident, quote, ToTokens};
+use quote::{format_ident, quote, quote_spanned, ToTokens};
 use syn::{
-    braced, parenthesized,
+    braced,
+    ext::IdentExt,
+    parenthesized,
     parse::{Parse, ParseStream},
     parse_macro_input, parse_quote,
     punctuated::Punctuated,
-    Error, Expr, GenericParam, Generics, Ident, ItemFn, LifetimeDef, Member, Pat, ReturnType,
-    Signature, Token, Type, TypeParam, Visibility,
+    AngleBracketedGenericArguments, Attribute, Block, ConstParam, Error, Expr, ExprClosure,
+    FieldsNamed, FnArg, GenericParam, Generics, Ident, ItemTrait, Lifetime, LifetimeDef, Member,
+    ParenthesizedGenericArguments, Pat, PatIdent, PatType, Receiver, ReturnType, Signature, Token,
+    TraitItem, TraitItemMethod, Type, TypeBareFn, TypeParam, TypeParamBound, TypePath,
+    TypeReference, Visibility,
 };
 
-fn quote_do(e: &Expr) -> Expr {
+/// The `Co` used to yield from the body of an effectful function when generators aren't available.
+/// It is hygienic so that the function body can't get its hands on it.
+#[cfg(not(feature = "nightly"))]
+fn co() -> Ident {
+    Ident::new("co", Span::mixed_site())
+}
+
+/// Suspend the generator that the expression appears in, yielding `value`.
+#[cfg(feature = "nightly")]
+fn quote_raw_yield(value: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    quote!(yield #value)
+}
+
+#[cfg(not(feature = "nightly"))]
+fn quote_raw_yield(value: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let co = co();
+    quote!(#co.yield_(#value).await)
+}
+
+fn quote_do(e: &Expr, span: Span, has_row: bool) -> Expr {
+    // frunk can't embed or subset an unknown row of effects, so only functions with one can use
+    // the indices that deal with rows
+    let index = if has_row {
+        quote!(_)
+    } else {
+        quote!(::effing_mad::macro_impl::Concrete<_>)
+    };
+    // point errors about missing effects at the `do_` rather than the whole function
+    let embed = quote_spanned!(span=> effs.do_embed::<_, #index>());
+    let injs = quote_raw_yield(quote!(effs));
+    // parenthesised so that it still parses at the start of a statement, e.g. `f().do_ + 1`
     parse_quote! {
-        {
-            use ::core::ops::{Generator, GeneratorState};
-            use ::effing_mad::frunk::coproduct::Coproduct;
-            let mut gen = #e;
-            let mut injection = Coproduct::inject(::effing_mad::injection::Begin);
+        ({
+            use ::effing_mad::generator::{Generator, GeneratorState};
+            use ::effing_mad::macro_impl::DoEffects;
+            let mut gen = ::core::pin::pin!(#e);
+            let mut injection = ::effing_mad::frunk::coproduct::CoprodInjector::inject(
+                ::effing_mad::injection::Begin,
+            );
             loop {
-                // safety: same as in `handle`
-                let pinned = unsafe { ::core::pin::Pin::new_unchecked(&mut gen) };
-                match pinned.resume(injection) {
-                    GeneratorState::Yielded(effs) =>
-                        injection = (yield effs.embed()).subset().ok().unwrap(),
+                match gen.as_mut().resume(injection) {
+                    GeneratorState::Yielded(effs) => {
+                        let (effs, subset_injs) = #embed;
+                        injection = subset_injs(#injs);
+                    }
                     GeneratorState::Complete(v) => break v,
                 }
             }
+        })
+    }
+}
+
+/// Yield an effect and get its injection. Static generators can lend out effects that borrow from
+/// them, which relies on a guard in their frame that can't move, so movable ones can only yield
+/// effects that they own. Like `do_`, the result is
+/// parenthesised so that it can start a statement.
+fn quote_yield(expr: &Expr, is_static: bool) -> Expr {
+    if is_static {
+        let yield_effect = quote_raw_yield(quote! {
+            ::effing_mad::macro_impl::YieldEffect::into_effs(effect, &guard)
+        });
+        parse_quote! {
+            ({
+                let effect = { #expr };
+                let guard = ::effing_mad::macro_impl::LendGuard::default();
+                let marker = ::effing_mad::macro_impl::mark(&effect);
+                let injs = #yield_effect;
+                ::core::mem::drop(guard);
+                ::effing_mad::macro_impl::get_inj(injs, marker).unwrap()
+            })
+        }
+    } else {
+        let yield_effect = quote_raw_yield(quote! {
+            ::effing_mad::macro_impl::YieldOwned::into_owned_effs(effect)
+        });
+        parse_quote! {
+            ({
+                let effect = { #expr };
+                let marker = ::effing_mad::macro_impl::mark(&effect);
+                let injs = #yield_effect;
+                ::effing_mad::macro_impl::get_inj(injs, marker).unwrap()
+            })
         }
     }
 }
 
 struct Effectful {
     effects: Punctuated<Type, Token![,]>,
+    // an open row of other effects, written last as `..E`
+    row: Option<Type>,
+    // extra bounds on the returned generator, written after a semicolon, e.g. `; Clone`
+    bounds: Punctuated<TypeParamBound, Token![+]>,
+    // whether the body pins callees to the generator's stack with `.do_`
+    uses_do: bool,
+    // errors found while lowering the body, which are reported instead of the function
+    errors: Vec<Error>,
 }
 
 impl Parse for Effectful {
     fn parse(input: ParseStream) -> Result<Self, Error> {
-        let effects = Punctuated::parse_terminated(input)?;
-        Ok(Effectful { effects })
+        let mut effects = Punctuated::new();
+        let mut row = None;
+        // `effectful!` ends the effects with `=>`, where the closure or block starts
+        let at_end = |input: ParseStream| {
+            input.is_empty() || input.peek(Token![;]) || input.peek(Token![=>])
+        };
+        while !at_end(input) {
+            if input.peek(Token![..]) {
+                <Token![..]>::parse(input)?;
+                let row_type: Type = input.parse()?;
+                if !at_end(input) {
+                    return Err(Error::new_spanned(
+                        row_type,
+                        "the row of effects must come last",
+                    ));
+                }
+                row = Some(row_type);
+                break;
+            }
+            effects.push_value(input.parse()?);
+            if at_end(input) {
+                break;
+            }
+            effects.push_punct(input.parse()?);
+        }
+        let mut bounds = Punctuated::new();
+        if input.peek(Token![;]) {
+            <Token![;]>::parse(input)?;
+            while !input.is_empty() && !input.peek(Token![=>]) {
+                bounds.push_value(input.parse()?);
+                if input.is_empty() || input.peek(Token![=>]) {
+                    break;
+                }
+                bounds.push_punct(input.parse()?);
+            }
+        }
+        Ok(Effectful {
+            effects,
+            row,
+            bounds,
+            uses_do: false,
+            errors: Vec::new(),
+        })
+    }
+}
+
+impl Effectful {
+    /// The coproduct of effects that the generator yields. The concrete effects go in front of the
+    /// row, if there is one.
+    fn yield_type(&self) -> proc_macro2::TokenStream {
+        let mut yield_type = match self.row {
+            Some(ref row) => row.to_token_stream(),
+            None => quote!(::effing_mad::frunk::coproduct::CNil),
+        };
+        for effect in &self.effects {
+            yield_type = quote! {
+                <#effect as ::effing_mad::macro_impl::EffectSet<#yield_type>>::Out
+            };
+        }
+        yield_type
+    }
+
+    /// The `Clone` bound on the generator, if it asks for one. Generators are static so that the
+    /// body can hold borrows of its locals across `yield`s, but static generators can't be `Clone`,
+    /// so those that ask to be are left movable.
+    fn clone_bound(&self) -> Option<&TypeParamBound> {
+        self.bounds.iter().find(|bound| match bound {
+            TypeParamBound::Trait(bound) => {
+                matches!(bound.path.segments.last(), Some(segment) if segment.ident == "Clone")
+            }
+            TypeParamBound::Lifetime(_) => false,
+        })
+    }
+
+    /// The first `Lent<E>` in the effect list, if there is one
+    fn lent_effect(&self) -> Option<&Type> {
+        self.effects.iter().find(|effect| match effect {
+            Type::Path(TypePath { path, .. }) => {
+                matches!(path.segments.last(), Some(segment) if segment.ident == "Lent")
+            }
+            _ => false,
+        })
+    }
+
+    /// The `Throw<E>` effects in the effect list, which `?` throws errors with. Only paths that can
+    /// name `effing_mad::std_effects::Throw` count, like `Throw<E>` or `std_effects::Throw<E>`, so
+    /// that other effects called `Throw` can be told apart by writing them with their module.
+    fn thrown_effects(&self) -> Vec<&Type> {
+        const THROW_PATH: [&str; 3] = ["effing_mad", "std_effects", "Throw"];
+        self.effects
+            .iter()
+            .filter(|effect| {
+                let Type::Path(path) = effect else {
+                    return false;
+                };
+                let segments = &path.path.segments;
+                let leading_colon = path.path.leading_colon.is_some();
+                path.qself.is_none()
+                    && segments.len() <= THROW_PATH.len()
+                    && (!leading_colon || segments.len() == THROW_PATH.len())
+                    && segments
+                        .iter()
+                        .zip(&THROW_PATH[THROW_PATH.len() - segments.len()..])
+                        .all(|(segment, name)| segment.ident == name)
+            })
+            .collect()
     }
 }
 
 impl syn::fold::Fold for Effectful {
     fn fold_expr(&mut self, e: Expr) -> Expr {
+        // closures and async blocks are bodies of their own, which the effectful function can't
+        // suspend from, and `?` inside them leaves them rather than the function
+        if let Expr::Closure(_) | Expr::Async(_) = e {
+            syn::visit::visit_expr(
+                &mut NestedSuspends {
+                    errors: &mut self.errors,
+                },
+                &e,
+            );
+            return e;
+        }
+        // lower any yields and do_s nested inside this expression first
+        let e = syn::fold::fold_expr(self, e);
+
         match e {
             Expr::Field(ref ef) => {
                 let Member::Named(ref name) = ef.member else { return e };
                 if name == "do_" {
-                    quote_do(&ef.base)
+                    self.uses_do = true;
+                    quote_do(&ef.base, name.span(), self.row.is_some())
                 } else {
                     e
                 }
             }
-            Expr::Yield(ref y) => {
-                let Some(ref expr) = y.expr else { panic!("no expr?") };
-                parse_quote! {
-                    {
-                        let into_effect = { #expr };
-                        let marker = ::effing_mad::macro_impl::mark(&into_effect);
-                        let effect = ::effing_mad::IntoEffect::into_effect(into_effect);
-                        let marker2 = ::effing_mad::macro_impl::mark(&effect);
-                        let injs = yield ::effing_mad::frunk::coproduct::Coproduct::inject(effect);
-                        let injs = ::effing_mad::macro_impl::get_inj(injs, marker2).unwrap();
-                        ::effing_mad::macro_impl::get_inj2(injs, marker).unwrap()
+            Expr::Yield(ref y) => match y.expr {
+                Some(ref expr) => quote_yield(expr, self.clone_bound().is_none()),
+                None => {
+                    self.errors.push(Error::new_spanned(
+                        y.yield_token,
+                        "`yield` needs an effect to yield, e.g. `yield Log(message)`",
+                    ));
+                    e
+                }
+            },
+            // `yield_!(expr)` is the same as `yield expr`, but can be written on stable Rust
+            Expr::Macro(ref m) if m.mac.path.is_ident("yield_") => match m.mac.parse_body() {
+                Ok(expr) => {
+                    let expr = self.fold_expr(expr);
+                    quote_yield(&expr, self.clone_bound().is_none())
+                }
+                Err(error) => {
+                    self.errors.push(error);
+                    e
+                }
+            },
+            // `?` on a `Result` throws the error as an effect, if the function can throw
+            Expr::Try(ref t) => {
+                let effect = match self.thrown_effects()[..] {
+                    [] => return e,
+                    [effect] => effect,
+                    _ => {
+                        self.errors.push(Error::new_spanned(
+                            t.question_token,
+                            "`?` is ambiguous in a function with several `Throw` effects",
+                        ));
+                        return e;
                     }
+                };
+                let inner = &t.expr;
+                let throw = quote_yield(
+                    &parse_quote! {
+                        <#effect as ::effing_mad::macro_impl::Thrown>::throw(err)
+                    },
+                    self.clone_bound().is_none(),
+                );
+                // parenthesised so that it still parses at the start of a statement, e.g. `a? + b`
+                parse_quote! {
+                    (match ::effing_mad::macro_impl::TryThrow::<
+                        <#effect as ::effing_mad::macro_impl::Thrown>::Error,
+                    >::branch(#inner) {
+                        ::effing_mad::macro_impl::Branch::Continue(v) => v,
+                        ::effing_mad::macro_impl::Branch::Throw(err) => match #throw {},
+                        ::effing_mad::macro_impl::Branch::Return(r) => {
+                            return ::effing_mad::macro_impl::Residual::into_return(r)
+                        }
+                    })
                 }
             }
             e => e,
@@ -74,55 +309,366 @@ impl syn::fold::Fold for Effectful {
     }
 }
 
+/// Finds `yield`s and `.do_`s inside a closure or async block in an effectful body. They would
+/// suspend the closure or block rather than the function, so they are reported as errors.
+struct NestedSuspends<'a> {
+    errors: &'a mut Vec<Error>,
+}
+
+impl NestedSuspends<'_> {
+    fn report(&mut self, tokens: impl ToTokens, what: &str) {
+        self.errors.push(Error::new_spanned(
+            tokens,
+            format!(
+                "`{what}` can not be used inside a closure or async block in an effectful \
+                 function, since only the function itself can yield effects"
+            ),
+        ));
+    }
+}
+
+impl<'ast> syn::visit::Visit<'ast> for NestedSuspends<'_> {
+    fn visit_expr_yield(&mut self, y: &'ast syn::ExprYield) {
+        self.report(y.yield_token, "yield");
+        syn::visit::visit_expr_yield(self, y);
+    }
+
+    fn visit_expr_macro(&mut self, m: &'ast syn::ExprMacro) {
+        if m.mac.path.is_ident("yield_") {
+            self.report(&m.mac.path, "yield_!");
+        }
+        syn::visit::visit_expr_macro(self, m);
+    }
+
+    fn visit_expr_field(&mut self, ef: &'ast syn::ExprField) {
+        if matches!(ef.member, Member::Named(ref name) if name == "do_") {
+            self.report(&ef.member, "do_");
+        }
+        syn::visit::visit_expr_field(self, ef);
+    }
+}
+
+/// Names the elided lifetimes in a function's arguments, so that the generator it returns can be
+/// said to capture them. Like `async fn`, the generator captures every lifetime in the arguments,
+/// since the body can hold on to anything it was given. Elided lifetimes in `Fn(&T)` and
+/// `fn(&T)` are left alone, since they are higher-ranked rather than lifetimes of the function.
+/// Lifetimes that are left out entirely, like the one in `Formatter`, can't be seen by a macro, so
+/// they have to be written as `Formatter<'_>`.
+#[derive(Default)]
+struct NameLifetimes {
+    named: Vec<Lifetime>,
+}
+
+impl NameLifetimes {
+    fn fresh(&mut self, span: Span) -> Lifetime {
+        let lifetime = Lifetime::new(&format!("'__effectful{}", self.named.len()), span);
+        self.named.push(lifetime.clone());
+        lifetime
+    }
+}
+
+impl syn::fold::Fold for NameLifetimes {
+    fn fold_lifetime(&mut self, lifetime: Lifetime) -> Lifetime {
+        if lifetime.ident == "_" {
+            self.fresh(lifetime.apostrophe)
+        } else {
+            lifetime
+        }
+    }
+
+    fn fold_receiver(&mut self, mut receiver: Receiver) -> Receiver {
+        if let Some((and, ref mut lifetime @ None)) = receiver.reference {
+            *lifetime = Some(self.fresh(and.span));
+        }
+        syn::fold::fold_receiver(self, receiver)
+    }
+
+    fn fold_type_reference(&mut self, mut reference: TypeReference) -> TypeReference {
+        if reference.lifetime.is_none() {
+            reference.lifetime = Some(self.fresh(reference.and_token.span));
+        }
+        syn::fold::fold_type_reference(self, reference)
+    }
+
+    fn fold_type_bare_fn(&mut self, bare_fn: TypeBareFn) -> TypeBareFn {
+        bare_fn
+    }
+
+    fn fold_parenthesized_generic_arguments(
+        &mut self,
+        args: ParenthesizedGenericArguments,
+    ) -> ParenthesizedGenericArguments {
+        args
+    }
+}
+
+/// A function for `#[effectful]` to transform. This is either a free function, a method in an `impl`
+/// block, or a method in a trait, which might not have a body.
+struct EffectfulFn {
+    attrs: Vec<Attribute>,
+    vis: Visibility,
+    sig: Signature,
+    body: Option<Block>,
+}
+
+impl Parse for EffectfulFn {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let attrs = input.call(Attribute::parse_outer)?;
+        let vis = input.parse()?;
+        let sig = input.parse()?;
+        let body = if input.peek(Token![;]) {
+            <Token![;]>::parse(input)?;
+            None
+        } else {
+            Some(input.parse()?)
+        };
+        Ok(EffectfulFn {
+            attrs,
+            vis,
+            sig,
+            body,
+        })
+    }
+}
+
 #[proc_macro_attribute]
 pub fn effectful(args: TokenStream, item: TokenStream) -> TokenStream {
     let mut effects = parse_macro_input!(args as Effectful);
-    let effect_names = &effects.effects;
-    let mut yield_type = quote! {
-        ::effing_mad::frunk::coproduct::CNil
-    };
-    for effect in effect_names {
-        yield_type = quote! {
-            <#effect as ::effing_mad::macro_impl::EffectSet<#yield_type>>::Out
-        };
-    }
-    let ItemFn {
+    let bounds = effects.bounds.iter().cloned().collect::<Vec<_>>();
+    let yield_type = effects.yield_type();
+    let EffectfulFn {
         attrs,
         vis,
         sig,
-        block,
-    } = parse_macro_input!(item as ItemFn);
+        body,
+    } = parse_macro_input!(item as EffectfulFn);
     let Signature {
         constness,
         unsafety,
         ident,
-        generics,
+        mut generics,
         inputs,
         output,
         ..
     } = sig;
+    // the generator holds on to the arguments, so it borrows for every lifetime in them. `+ 'a`
+    // would say that it outlives each of them instead, which it doesn't if there are two
+    let mut name_lifetimes = NameLifetimes::default();
+    let inputs = inputs
+        .into_iter()
+        .map(|arg| syn::fold::fold_fn_arg(&mut name_lifetimes, arg))
+        .collect::<Punctuated<_, Token![,]>>();
+    let first_non_lifetime = generics.lifetimes().count();
+    for (i, lifetime) in name_lifetimes.named.into_iter().enumerate() {
+        let param = GenericParam::Lifetime(LifetimeDef::new(lifetime));
+        generics.params.insert(first_non_lifetime + i, param);
+    }
+    let captures = generics
+        .lifetimes()
+        .map(|def| def.lifetime.clone())
+        .collect::<Vec<_>>();
+    if let Some(ref row) = effects.row {
+        generics
+            .make_where_clause()
+            .predicates
+            .push(parse_quote!(#row: ::effing_mad::injection::InjectionList));
+    }
+    let where_clause = &generics.where_clause;
     let return_type = match output {
         ReturnType::Default => quote!(()),
         ReturnType::Type(_r_arrow, ref ty) => ty.to_token_stream(),
     };
-    let new_block = syn::fold::fold_block(&mut effects, *block);
+    let injs_type = quote! {
+        <#yield_type as ::effing_mad::injection::InjectionList>::List
+    };
+    let body = match body {
+        Some(body) => {
+            let new_block = syn::fold::fold_block(&mut effects, body);
+            if !effects.errors.is_empty() {
+                let errors = effects.errors.iter().map(Error::to_compile_error);
+                return quote!(#(#errors)*).into();
+            }
+            // movable generators can't use `.do_`, since it pins the callee to their stack
+            let clone_bound = effects.clone_bound();
+            if let Some(clone_bound) = clone_bound.filter(|_| effects.uses_do) {
+                return Error::new_spanned(
+                    clone_bound,
+                    "`Clone` effectful functions can not use `.do_`, since it borrows the callee \
+                     across `yield`s",
+                )
+                .into_compile_error()
+                .into();
+            }
+            // nor can they lend effects, since they could be moved while one is lent out
+            if let Some(lent) = clone_bound.and(effects.lent_effect()) {
+                return Error::new_spanned(
+                    lent,
+                    "`Clone` effectful functions can not lend effects, since they can be moved \
+                     while an effect is lent out",
+                )
+                .into_compile_error()
+                .into();
+            }
+            let generator =
+                quote_generator(&yield_type, &injs_type, new_block, clone_bound.is_none());
+            quote!({ #generator })
+        }
+        None => quote!(;),
+    };
     quote! {
         #(#attrs)*
         #vis #constness #unsafety
         fn #ident #generics(#inputs)
-        -> impl ::core::ops::Generator<
-            <#yield_type as ::effing_mad::injection::InjectionList>::List,
+        -> impl ::effing_mad::generator::Generator<
+            #injs_type,
             Yield = #yield_type,
             Return = #return_type
-        > {
-            move |_begin: <#yield_type as ::effing_mad::injection::InjectionList>::List| {
-                #new_block
+        > #(+ #bounds)* #(+ ::effing_mad::macro_impl::Captures<#captures>)*
+        #where_clause
+        #body
+    }
+    .into()
+}
+
+/// What `effectful!` turns into a generator: either a closure that returns one, or a block
+enum EffectfulExpr {
+    Closure(ExprClosure),
+    Block(Block),
+}
+
+struct EffectfulMacro {
+    effects: Effectful,
+    expr: EffectfulExpr,
+}
+
+impl Parse for EffectfulMacro {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let effects: Effectful = input.parse()?;
+        if let Some(bound) = effects.bounds.first() {
+            return Err(Error::new_spanned(
+                bound,
+                "bounds on the generator can only be given to `#[effectful]`",
+            ));
+        }
+        <Token![=>]>::parse(input)?;
+        let closure_start = input.peek(Token![|])
+            || input.peek(Token![||])
+            || input.peek(Token![move])
+            || input.peek(Token![static])
+            || input.peek(Token![async]);
+        let expr = if closure_start {
+            EffectfulExpr::Closure(input.parse()?)
+        } else {
+            EffectfulExpr::Block(Block {
+                brace_token: Default::default(),
+                stmts: Block::parse_within(input)?,
+            })
+        };
+        Ok(EffectfulMacro { effects, expr })
+    }
+}
+
+/// The expression version of `#[effectful]`. `effectful!(A, B => |x| ...)` is a closure that
+/// returns an effectful computation, and `effectful!(A, B => ...)` is an effectful computation
+/// running the statements after the `=>`. Their bodies can use `yield`, `yield_!` and `.do_` just
+/// like the body of an effectful function. The generator takes ownership of anything the body
+/// uses, like a `move` closure would.
+#[proc_macro]
+pub fn effectful_expr(input: TokenStream) -> TokenStream {
+    let EffectfulMacro { mut effects, expr } = parse_macro_input!(input as EffectfulMacro);
+    let yield_type = effects.yield_type();
+    let injs_type = quote! {
+        <#yield_type as ::effing_mad::injection::InjectionList>::List
+    };
+    let (closure, body) = match expr {
+        EffectfulExpr::Closure(closure) => {
+            if let Some(asyncness) = closure.asyncness {
+                return Error::new_spanned(asyncness, "effectful closures can not be async")
+                    .into_compile_error()
+                    .into();
             }
+            if let Some(movability) = closure.movability {
+                return Error::new_spanned(movability, "effectful closures can not be static")
+                    .into_compile_error()
+                    .into();
+            }
+            let body = &closure.body;
+            // the closure's return type is the return type of the generator it returns
+            let body = match closure.output {
+                ReturnType::Default => parse_quote!({ #body }),
+                ReturnType::Type(_, ref ty) => parse_quote!({
+                    let ret: #ty = #body;
+                    ret
+                }),
+            };
+            (Some(closure), body)
         }
+        EffectfulExpr::Block(block) => (None, block),
+    };
+    let new_block = syn::fold::fold_block(&mut effects, body);
+    if !effects.errors.is_empty() {
+        let errors = effects.errors.iter().map(Error::to_compile_error);
+        return quote!(#(#errors)*).into();
+    }
+    let generator = quote_generator(&yield_type, &injs_type, new_block, true);
+    let generator = quote! {
+        ::effing_mad::macro_impl::with_effects::<#yield_type, _>({ #generator })
+    };
+    match closure {
+        Some(ExprClosure {
+            attrs,
+            capture,
+            inputs,
+            ..
+        }) => quote! {
+            #(#attrs)*
+            #capture |#inputs| #generator
+        },
+        None => generator,
     }
     .into()
 }
 
+#[cfg(feature = "nightly")]
+fn quote_generator(
+    _yield_type: &proc_macro2::TokenStream,
+    injs_type: &proc_macro2::TokenStream,
+    body: Block,
+    is_static: bool,
+) -> proc_macro2::TokenStream {
+    if is_static {
+        quote! {
+            static move |_begin: #injs_type| {
+                #body
+            }
+        }
+    } else {
+        quote! {
+            move |_begin: #injs_type| {
+                #body
+            }
+        }
+    }
+}
+
+// async blocks can always borrow from themselves, so there's nothing to do for `is_static`
+#[cfg(not(feature = "nightly"))]
+fn quote_generator(
+    yield_type: &proc_macro2::TokenStream,
+    injs_type: &proc_macro2::TokenStream,
+    body: Block,
+    _is_static: bool,
+) -> proc_macro2::TokenStream {
+    let co = co();
+    quote! {
+        // safety: the body only awaits `co`'s futures where they are made, since yields inside
+        // closures and async blocks are rejected rather than lowered, so none of them can be
+        // carried out of this generator. `co` is hygienic, so the body can't name it either
+        let #co = unsafe { ::effing_mad::generator::Co::<#yield_type, #injs_type>::new() };
+        ::effing_mad::generator::Gen::new(#co, async move #body)
+    }
+}
+
 struct EffectArg {
     name: Ident,
     ty: Type,
@@ -138,13 +684,17 @@ impl Parse for EffectArg {
 }
 
 struct Effect {
+    attrs: Vec<Attribute>,
     name: Ident,
     args: Vec<EffectArg>,
     ret: Type,
+    /// The default implementation of the operation in the handler trait, for `#[effect]`
+    default: Option<Block>,
 }
 
 impl Parse for Effect {
     fn parse(input: ParseStream) -> syn::Result<Self> {
+        let attrs = input.call(Attribute::parse_outer)?;
         <Token![fn]>::parse(input)?;
         let name = input.parse()?;
 
@@ -153,14 +703,26 @@ impl Parse for Effect {
         let args = Punctuated::<EffectArg, Token![,]>::parse_terminated(&content)?;
         let args = args.into_iter().collect();
 
-        <Token![->]>::parse(input)?;
-        let ret = input.parse()?;
+        // like a normal function, an effect that returns nothing can leave out `-> ()`
+        let ret = if input.peek(Token![->]) {
+            <Token![->]>::parse(input)?;
+            input.parse()?
+        } else {
+            parse_quote!(())
+        };
 
-        Ok(Effect { name, args, ret })
+        Ok(Effect {
+            attrs,
+            name,
+            args,
+            ret,
+            default: None,
+        })
     }
 }
 
 struct Effects {
+    attrs: Vec<Attribute>,
     vis: Visibility,
     mod_name: Ident,
     eff_name: Ident,
@@ -170,6 +732,7 @@ struct Effects {
 
 impl Parse for Effects {
     fn parse(input: ParseStream) -> syn::Result<Self> {
+        let attrs = input.call(Attribute::parse_outer)?;
         let vis = input.parse()?;
         let mod_name = input.parse()?;
         <Token![::]>::parse(input)?;
@@ -181,6 +744,7 @@ impl Parse for Effects {
         let effects = Punctuated::parse_terminated(&content)?;
 
         Ok(Effects {
+            attrs,
             vis,
             mod_name,
             eff_name,
@@ -192,14 +756,22 @@ impl Parse for Effects {
 
 #[proc_macro]
 pub fn effects(input: TokenStream) -> TokenStream {
-    let Effects {
+    quote_effects(&parse_macro_input!(input as Effects)).into()
+}
+
+/// The module for a group of effects, shared by `effects!` and `#[effect]`
+fn quote_effects(
+    Effects {
+        attrs,
         vis,
         mod_name,
         eff_name,
         generics,
         effects,
-    } = parse_macro_input!(input as Effects);
-    let injs_name = Ident::new(&format!("{}Injs", eff_name), Span::call_site());
+    }: &Effects,
+) -> proc_macro2::TokenStream {
+    let injs_name = format_ident!("{eff_name}Injs");
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
 
     let variants = effects
         .iter()
@@ -210,25 +782,48 @@ pub fn effects(input: TokenStream) -> TokenStream {
         .map(|Effect { name, .. }| format_ident!("__{name}"))
         .collect::<Vec<_>>();
     let eff_names = effects.iter().map(|eff| &eff.name).collect::<Vec<_>>();
+    let operations = eff_names.iter().map(|name| name.to_string());
+    // public names for the operations as effects of their own, e.g. `Put<S>` for `__put<S>`. An
+    // operation named after its group, like `log` in `Log`, doesn't get one
+    let aliased = effects
+        .iter()
+        .zip(&structs)
+        .map(|(eff, name)| (eff, name, camel_case(&eff.name)))
+        .filter(|(_, _, alias)| alias != eff_name && *alias != injs_name)
+        .collect::<Vec<_>>();
+    let alias_attrs = aliased.iter().map(|(eff, ..)| &eff.attrs);
+    let alias_structs = aliased.iter().map(|(_, name, _)| name);
+    let aliases = aliased.iter().map(|(.., alias)| alias);
+    let alias_docs = aliased.iter().map(|(eff, ..)| {
+        let name = &eff.name;
+        format!("The `{name}` operation of [`{eff_name}`], as an effect of its own.")
+    });
+    // bounds on a type alias aren't checked, so the alias only gets the parameters themselves
+    let alias_params = generics.params.iter().map(|param| match param {
+        GenericParam::Type(TypeParam { ident, .. }) => quote!(#ident),
+        GenericParam::Lifetime(LifetimeDef { lifetime, .. }) => quote!(#lifetime),
+        GenericParam::Const(ConstParam { ident, ty, .. }) => quote!(const #ident: #ty),
+    });
+    let alias_generics = quote!(<#(#alias_params),*>);
+    // const parameters can go unused, so they don't need any `PhantomData`
     let phantom_data_tys = generics
         .params
         .iter()
-        .map(|param| match param {
+        .filter_map(|param| match param {
             GenericParam::Type(TypeParam { ident, .. }) => {
-                quote!(::core::marker::PhantomData<#ident>)
+                Some(quote!(::core::marker::PhantomData<#ident>))
             }
             GenericParam::Lifetime(LifetimeDef { lifetime, .. }) => {
-                quote!(::core::marker::PhantomData<&#lifetime ()>)
+                Some(quote!(::core::marker::PhantomData<&#lifetime ()>))
             }
-            syn::GenericParam::Const(_) => todo!(),
+            GenericParam::Const(_) => None,
         })
         .collect::<Vec<_>>();
-    let phantom_data_tys = quote!(#(#phantom_data_tys),*);
-    let phantom_datas = generics
-        .params
+    let phantom_datas = phantom_data_tys
         .iter()
         .map(|_| quote!(::core::marker::PhantomData))
         .collect::<Vec<_>>();
+    let phantom_data_tys = quote!(#(pub #phantom_data_tys),*);
     let phantom_datas = quote!(#(#phantom_datas),*);
 
     let arg_name = effects
@@ -240,54 +835,99 @@ pub fn effects(input: TokenStream) -> TokenStream {
         .map(|eff| eff.args.iter().map(|arg| &arg.ty).collect::<Vec<_>>())
         .collect::<Vec<_>>();
     let ret_ty = effects.iter().map(|eff| &eff.ret).collect::<Vec<_>>();
+    let eff_attrs = effects.iter().map(|eff| &eff.attrs).collect::<Vec<_>>();
 
     quote! {
         /// An effect definition.
         ///
         /// To handle this effect, use the `handler!` macro.
         #vis mod #mod_name {
+            /// The operations in this group, for `handler!` to check it has an arm for each one
+            #[doc(hidden)]
+            pub const __OPERATIONS: &[&str] = &[#(#operations),*];
+
+            #(#attrs)*
             #[allow(non_camel_case_types)]
-            pub enum #eff_name #generics {
+            pub enum #eff_name #generics #where_clause {
                 #(
                 #variants(#(#arg_ty),*)
                 ),*
             }
 
             #[allow(non_camel_case_types)]
-            pub enum #injs_name #generics {
+            pub enum #injs_name #generics #where_clause {
                 #(
                 #variants(#ret_ty)
                 ),*
             }
 
-            impl #generics #eff_name #generics {
+            impl #impl_generics #eff_name #ty_generics #where_clause {
                 #(
-                pub fn #eff_names(#(#arg_name: #arg_ty),*) -> #structs #generics {
+                #(#eff_attrs)*
+                pub fn #eff_names(#(#arg_name: #arg_ty),*) -> #structs #ty_generics {
                     #structs(#(#arg_name,)* #phantom_datas)
                 }
                 )*
             }
 
-            impl #generics ::effing_mad::Effect for #eff_name #generics {
-                type Injection = #injs_name #generics;
+            impl #impl_generics ::effing_mad::Effect for #eff_name #ty_generics #where_clause {
+                type Injection = #injs_name #ty_generics;
+            }
+
+            impl #impl_generics ::effing_mad::EffectGroup for #eff_name #ty_generics
+            #where_clause
+            {
+                type Members = ::effing_mad::frunk::Coprod!(#(#structs #ty_generics),*);
+
+                fn into_members(self) -> Self::Members {
+                    match self {
+                        #(
+                        #eff_name::#variants(#(#arg_name),*) =>
+                            ::effing_mad::frunk::coproduct::Coproduct::inject(
+                                #structs(#(#arg_name,)* #phantom_datas)
+                            ),
+                        )*
+                    }
+                }
+                fn into_member_injs(
+                    injs: #injs_name #ty_generics,
+                ) -> <Self::Members as ::effing_mad::injection::InjectionList>::List {
+                    match injs {
+                        #(
+                        #injs_name::#variants(inj) =>
+                            ::effing_mad::frunk::coproduct::Coproduct::inject(
+                                ::effing_mad::injection::Tagged::<#ret_ty, #structs #ty_generics>::new(inj)
+                            ),
+                        )*
+                    }
+                }
             }
 
             #(
+            #(#eff_attrs)*
             #[allow(non_camel_case_types)]
-            pub struct #structs #generics(#(#arg_ty,)* #phantom_data_tys);
+            pub struct #structs #generics(#(pub #arg_ty,)* #phantom_data_tys) #where_clause;
+
 
-            impl #generics ::effing_mad::IntoEffect for #structs #generics {
-                type Effect = #eff_name #generics;
+            // each operation is also an effect by itself, for when it's handled on its own
+            impl #impl_generics ::effing_mad::Effect for #structs #ty_generics #where_clause {
+                type Injection = #ret_ty;
+            }
+
+            impl #impl_generics ::effing_mad::IntoEffect for #structs #ty_generics
+            #where_clause
+            {
+                type Effect = #eff_name #ty_generics;
                 type Injection = #ret_ty;
 
                 fn into_effect(self) -> Self::Effect {
                     let #structs(#(#arg_name,)* ..) = self;
                     #eff_name::#variants(#(#arg_name),*)
                 }
-                fn inject(inj: #ret_ty) -> #injs_name #generics {
+                fn inject(inj: #ret_ty) -> #injs_name #ty_generics {
                     #injs_name::#variants(inj)
                 }
-                fn uninject(injs: #injs_name #generics) -> Option<#ret_ty> {
+                fn uninject(injs: #injs_name #ty_generics) -> Option<#ret_ty> {
                     match injs {
                         #injs_name::#variants(inj) => Some(inj),
                         _ => None,
@@ -295,6 +935,269 @@ pub fn effects(input: TokenStream) -> TokenStream {
                 }
             }
             )*
+
+            #(
+            #[doc = #alias_docs]
+            #[doc = ""]
+            #(#alias_attrs)*
+            pub type #aliases #alias_generics = #alias_structs #ty_generics;
+            )*
+        }
+    }
+}
+
+/// `read_line` to `ReadLine`, for the name of an operation as an effect of its own
+fn camel_case(ident: &Ident) -> Ident {
+    let mut name = String::new();
+    let mut after_underscore = true;
+    for c in ident.unraw().to_string().chars() {
+        if c == '_' {
+            after_underscore = true;
+        } else if after_underscore {
+            name.extend(c.to_uppercase());
+            after_underscore = false;
+        } else {
+            name.push(c);
+        }
+    }
+    Ident::new(&name, ident.span())
+}
+
+/// `Console` to `console`, for the module `#[effect]` puts a group in
+fn snake_case(ident: &Ident) -> Ident {
+    let mut name = String::new();
+    let mut after_lower = false;
+    for c in ident.to_string().chars() {
+        if c.is_uppercase() && after_lower {
+            name.push('_');
+        }
+        after_lower = c.is_lowercase() || c.is_ascii_digit();
+        name.extend(c.to_lowercase());
+    }
+    Ident::new(&name, ident.span())
+}
+
+impl TryFrom<TraitItem> for Effect {
+    type Error = Error;
+
+    fn try_from(item: TraitItem) -> syn::Result<Self> {
+        let TraitItem::Method(TraitItemMethod {
+            attrs,
+            sig,
+            default,
+            ..
+        }) = item
+        else {
+            return Err(Error::new_spanned(
+                item,
+                "effect traits can only contain operations, which are `fn`s",
+            ));
+        };
+        if let Some(qualifier) = sig
+            .constness
+            .map(ToTokens::into_token_stream)
+            .or_else(|| sig.asyncness.map(ToTokens::into_token_stream))
+            .or_else(|| sig.unsafety.map(ToTokens::into_token_stream))
+            .or_else(|| sig.abi.map(ToTokens::into_token_stream))
+        {
+            return Err(Error::new_spanned(
+                qualifier,
+                "effect operations can not be `const`, `async`, `unsafe` or `extern`",
+            ));
+        }
+        if !sig.generics.params.is_empty() || sig.generics.where_clause.is_some() {
+            return Err(Error::new_spanned(
+                sig.generics,
+                "effect operations can not be generic, but the trait can be",
+            ));
+        }
+        if let Some(variadic) = sig.variadic {
+            return Err(Error::new_spanned(
+                variadic,
+                "effect operations can not be variadic",
+            ));
+        }
+
+        let args = sig
+            .inputs
+            .into_iter()
+            .map(|arg| match arg {
+                FnArg::Receiver(receiver) => Err(Error::new_spanned(
+                    receiver,
+                    "effect operations don't take `self`, they are handled by the handler trait",
+                )),
+                FnArg::Typed(PatType { pat, ty, .. }) => match *pat {
+                    Pat::Ident(PatIdent {
+                        by_ref: None,
+                        mutability: None,
+                        ident,
+                        subpat: None,
+                        ..
+                    }) => Ok(EffectArg {
+                        name: ident,
+                        ty: *ty,
+                    }),
+                    pat => Err(Error::new_spanned(
+                        pat,
+                        "effect operation arguments must be plain names, e.g. `message: String`",
+                    )),
+                },
+            })
+            .collect::<syn::Result<_>>()?;
+        let ret = match sig.output {
+            ReturnType::Default => parse_quote!(()),
+            ReturnType::Type(_, ty) => *ty,
+        };
+
+        Ok(Effect {
+            attrs,
+            name: sig.ident,
+            args,
+            ret,
+            default,
+        })
+    }
+}
+
+impl TryFrom<ItemTrait> for Effects {
+    type Error = Error;
+
+    fn try_from(item: ItemTrait) -> syn::Result<Self> {
+        if let Some(unsafety) = item.unsafety {
+            return Err(Error::new_spanned(
+                unsafety,
+                "effect traits can not be `unsafe`",
+            ));
+        }
+        if let Some(auto_token) = item.auto_token {
+            return Err(Error::new_spanned(
+                auto_token,
+                "effect traits can not be `auto`",
+            ));
+        }
+        if !item.supertraits.is_empty() {
+            return Err(Error::new_spanned(
+                item.supertraits,
+                "effect traits can not have supertraits",
+            ));
+        }
+
+        let effects = item
+            .items
+            .into_iter()
+            .map(Effect::try_from)
+            .collect::<syn::Result<_>>()?;
+
+        Ok(Effects {
+            attrs: item.attrs,
+            vis: item.vis,
+            mod_name: snake_case(&item.ident),
+            eff_name: item.ident,
+            generics: item.generics,
+            effects,
+        })
+    }
+}
+
+/// Define a group of effects with a trait. `#[effect] trait Console { ... }` generates the same
+/// `console` module as `effects! { console::Console { ... } }` would, brings `Console` into scope
+/// and generates a `ConsoleHandler` trait with a method for each operation. Operations with a body
+/// become provided methods of the handler trait. To handle `Console` with an implementation of
+/// `ConsoleHandler`, wrap it in `effing_mad::Handled`.
+#[proc_macro_attribute]
+pub fn effect(args: TokenStream, item: TokenStream) -> TokenStream {
+    if !args.is_empty() {
+        return Error::new_spanned(
+            proc_macro2::TokenStream::from(args),
+            "`#[effect]` doesn't take any arguments",
+        )
+        .into_compile_error()
+        .into();
+    }
+    let effects = match Effects::try_from(parse_macro_input!(item as ItemTrait)) {
+        Ok(effects) => effects,
+        Err(e) => return e.into_compile_error().into(),
+    };
+    let group = quote_effects(&effects);
+
+    let Effects {
+        vis,
+        mod_name,
+        eff_name,
+        generics,
+        effects,
+        ..
+    } = &effects;
+    let injs_name = format_ident!("{eff_name}Injs");
+    let handler_name = format_ident!("{eff_name}Handler");
+    let (_, ty_generics, where_clause) = generics.split_for_impl();
+    // `H` is the implementation of the handler trait, and `R` the return type of the computation
+    let mut impl_generics = generics.clone();
+    impl_generics
+        .params
+        .push(parse_quote!(H: #handler_name #ty_generics));
+    impl_generics.params.push(parse_quote!(R));
+    let (impl_generics, _, _) = impl_generics.split_for_impl();
+
+    let eff_attrs = effects.iter().map(|eff| &eff.attrs).collect::<Vec<_>>();
+    let eff_names = effects.iter().map(|eff| &eff.name).collect::<Vec<_>>();
+    let variants = eff_names
+        .iter()
+        .map(|name| format_ident!("__{name}"))
+        .collect::<Vec<_>>();
+    let arg_name = effects
+        .iter()
+        .map(|eff| eff.args.iter().map(|arg| &arg.name).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let arg_ty = effects
+        .iter()
+        .map(|eff| eff.args.iter().map(|arg| &arg.ty).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let ret_ty = effects.iter().map(|eff| &eff.ret).collect::<Vec<_>>();
+    let bodies = effects.iter().map(|eff| match &eff.default {
+        Some(body) => body.to_token_stream(),
+        None => quote!(;),
+    });
+    let handler_doc = format!(
+        "A handler for [`{eff_name}`], with a method for each of its operations. To pass an \
+         implementation to `handle`, wrap it in [`Handled`](::effing_mad::Handled)."
+    );
+
+    quote! {
+        #group
+
+        #vis use #mod_name::#eff_name;
+
+        #[doc = #handler_doc]
+        #vis trait #handler_name #generics #where_clause {
+            #(
+            #(#eff_attrs)*
+            fn #eff_names(&mut self, #(#arg_name: #arg_ty),*) -> #ret_ty #bodies
+            )*
+        }
+
+        impl #impl_generics ::effing_mad::Handler<#mod_name::#eff_name #ty_generics, R>
+            for ::effing_mad::Handled<H>
+        #where_clause
+        {
+            type Output = R;
+
+            fn handle(
+                &mut self,
+                eff: #mod_name::#eff_name #ty_generics,
+            ) -> ::core::ops::ControlFlow<R, #mod_name::#injs_name #ty_generics> {
+                ::core::ops::ControlFlow::Continue(match eff {
+                    #(
+                    #mod_name::#eff_name::#variants(#(#arg_name),*) => #mod_name::#injs_name::#variants(
+                        self.0.#eff_names(#(#arg_name),*)
+                    ),
+                    )*
+                })
+            }
+
+            fn on_return(&mut self, ret: R) -> R {
+                ret
+            }
         }
     }
     .into()
@@ -321,67 +1224,508 @@ impl Parse for HandlerArm {
     }
 }
 
-struct Handler {
-    asyncness: Option<Token![async]>,
-    moveness: Option<Token![move]>,
+struct ReturnArm {
+    ret: Pat,
+    output: Option<Type>,
+    body: Expr,
+}
+
+impl Parse for ReturnArm {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        <Token![return]>::parse(input)?;
+
+        let content;
+        parenthesized!(content in input);
+        let ret = content.parse()?;
+
+        let output = if input.peek(Token![->]) {
+            <Token![->]>::parse(input)?;
+            Some(input.parse()?)
+        } else {
+            None
+        };
+
+        <Token![=>]>::parse(input)?;
+        let body = input.parse()?;
+
+        Ok(ReturnArm { ret, output, body })
+    }
+}
+
+/// The arms of a handler for an effect group
+struct Arms {
+    arms: Vec<HandlerArm>,
+    /// `_ => breaker`, for every operation without an arm of its own
+    wildcard: Option<(Token![_], Expr)>,
+    /// `..` at the end, to forward every operation without an arm to an outer handler
+    forward: Option<Token![..]>,
+    return_arm: Option<ReturnArm>,
+}
+
+impl Parse for Arms {
+    /// Parse a comma separated list of handler arms, with at most one return arm and one `_` arm
+    /// among them
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut arms = Vec::new();
+        let mut wildcard = None;
+        let mut forward = None;
+        let mut return_arm = None;
+        while !input.is_empty() {
+            if input.peek(Token![return]) {
+                if return_arm.is_some() {
+                    return Err(input.error("a handler can only have one return arm"));
+                }
+                return_arm = Some(input.parse()?);
+            } else if input.peek(Token![_]) {
+                if wildcard.is_some() {
+                    return Err(input.error("a handler can only have one `_` arm"));
+                }
+                let underscore = input.parse()?;
+                <Token![=>]>::parse(input)?;
+                wildcard = Some((underscore, input.parse()?));
+            } else if input.peek(Token![..]) {
+                forward = Some(input.parse()?);
+                if !input.is_empty() {
+                    return Err(input.error("`..` must come after all of a handler's arms"));
+                }
+                break;
+            } else {
+                arms.push(input.parse()?);
+            }
+            if input.is_empty() {
+                break;
+            }
+            <Token![,]>::parse(input)?;
+        }
+        if let (Some((underscore, _)), Some(_)) = (&wildcard, &forward) {
+            return Err(Error::new_spanned(
+                underscore,
+                "a `_` arm handles every operation, so there is nothing left for `..` to forward",
+            ));
+        }
+        Ok(Arms {
+            arms,
+            wildcard,
+            forward,
+            return_arm,
+        })
+    }
+}
+
+impl Arms {
+    /// Handlers that aren't closures given to `handle_partial` have nowhere to forward effects to
+    fn check_no_forward(&self, handler: &str) -> syn::Result<()> {
+        match self.forward {
+            Some(forward) => Err(Error::new_spanned(
+                forward,
+                format!("{handler} can't forward operations to an outer handler"),
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The `mod_name::EffName<generic args>` that starts a handler for an effect group
+struct GroupName {
     mod_name: Ident,
     eff_name: Ident,
-    generics: Generics,
-    arms: Punctuated<HandlerArm, Token![,]>,
+    generics: Option<AngleBracketedGenericArguments>,
 }
 
-impl Parse for Handler {
+impl Parse for GroupName {
     fn parse(input: ParseStream) -> syn::Result<Self> {
-        let asyncness = input.parse()?;
-        let moveness = input.parse()?;
         let mod_name = input.parse()?;
         <Token![::]>::parse(input)?;
         let eff_name = input.parse()?;
-        let generics = input.parse()?;
-        <Token![,]>::parse(input)?;
-        let arms = Punctuated::parse_terminated(input)?;
-        Ok(Handler {
-            asyncness,
-            moveness,
+        let generics = if input.peek(Token![<]) {
+            Some(input.parse()?)
+        } else {
+            None
+        };
+        Ok(GroupName {
             mod_name,
             eff_name,
             generics,
+        })
+    }
+}
+
+/// Whether the input starts with a `GroupName` followed by the given token
+fn peek_group_name(input: ParseStream, then: impl FnOnce(ParseStream) -> bool) -> bool {
+    let fork = input.fork();
+    fork.parse::<GroupName>().is_ok() && then(&fork)
+}
+
+/// One item in a handler for several effects
+enum ManyItem {
+    /// `mod_name::EffName<generics> { arms }`, for a group from `effects!`
+    Group { name: GroupName, arms: Box<Arms> },
+    /// `Pattern => breaker`, for any other effect
+    Single { eff: Pat, breaker: Box<Expr> },
+}
+
+impl Parse for ManyItem {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if peek_group_name(input, |fork| fork.peek(syn::token::Brace)) {
+            let name = input.parse()?;
+            let content;
+            braced!(content in input);
+            let arms: Box<Arms> = content.parse()?;
+            arms.check_no_forward("handlers for several effects")?;
+            if let Some(ReturnArm { ret, .. }) = arms.return_arm {
+                return Err(Error::new_spanned(
+                    ret,
+                    "handlers for several effects can not have return arms",
+                ));
+            }
+            Ok(ManyItem::Group { name, arms })
+        } else {
+            let eff = input.parse()?;
+            <Token![=>]>::parse(input)?;
+            let breaker = input.parse()?;
+            Ok(ManyItem::Single { eff, breaker })
+        }
+    }
+}
+
+enum Handler {
+    /// A handler for one effect group, for `handle` and friends
+    Group {
+        asyncness: Option<Token![async]>,
+        moveness: Option<Token![move]>,
+        name: GroupName,
+        arms: Arms,
+    },
+    /// A named struct implementing `Handler` for one effect group
+    Struct {
+        vis: Visibility,
+        name: Ident,
+        generics: Generics,
+        fields: FieldsNamed,
+        group: GroupName,
+        arms: Arms,
+    },
+    /// A list of handlers sharing some state, for `handle_many`
+    Many {
+        moveness: Option<Token![move]>,
+        state: Option<(Pat, Type)>,
+        items: Punctuated<ManyItem, Token![,]>,
+    },
+}
+
+impl Parse for Handler {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if input.peek(Token![pub]) || input.peek(Token![struct]) {
+            let vis = input.parse()?;
+            <Token![struct]>::parse(input)?;
+            let name = input.parse()?;
+            let mut generics: Generics = input.parse()?;
+            generics.where_clause = input.parse()?;
+            let fields = input.parse()?;
+            <Token![for]>::parse(input)?;
+            let group = input.parse()?;
+            <Token![,]>::parse(input)?;
+            let arms: Arms = input.parse()?;
+            arms.check_no_forward("handler structs")?;
+            return Ok(Handler::Struct {
+                vis,
+                name,
+                generics,
+                fields,
+                group,
+                arms,
+            });
+        }
+
+        let asyncness = input.parse()?;
+        let moveness = input.parse()?;
+
+        if input.peek(Token![|]) || !peek_group_name(input, |fork| fork.peek(Token![,])) {
+            if let Some(asyncness) = asyncness {
+                return Err(Error::new_spanned(
+                    asyncness,
+                    "handlers for several effects can not be async",
+                ));
+            }
+            let state = if input.peek(Token![|]) {
+                <Token![|]>::parse(input)?;
+                let pat = input.parse()?;
+                <Token![:]>::parse(input)?;
+                let ty = input.parse()?;
+                <Token![|]>::parse(input)?;
+                Some((pat, ty))
+            } else {
+                None
+            };
+            let items = Punctuated::parse_terminated(input)?;
+            return Ok(Handler::Many {
+                moveness,
+                state,
+                items,
+            });
+        }
+
+        let name = input.parse()?;
+        <Token![,]>::parse(input)?;
+        let arms: Arms = input.parse()?;
+        if asyncness.is_some() {
+            arms.check_no_forward("async handlers")?;
+        }
+        if let (Some(forward), Some(_)) = (arms.forward, &arms.return_arm) {
+            return Err(Error::new_spanned(
+                forward,
+                "handlers that forward operations can not have return arms",
+            ));
+        }
+
+        Ok(Handler::Group {
+            asyncness,
+            moveness,
+            name,
             arms,
         })
     }
 }
 
-#[proc_macro]
-pub fn handler(input: TokenStream) -> TokenStream {
-    let Handler {
-        asyncness,
-        moveness,
-        mod_name,
-        eff_name,
-        generics,
-        arms,
-    } = parse_macro_input!(input as Handler);
+/// Brings a group and its injections into scope for `quote_group_match`. The operations' own
+/// effects are left out, so that they don't shadow anything the handler's arms use.
+fn quote_use_group(mod_name: &Ident, eff_name: &Ident) -> proc_macro2::TokenStream {
     let injs_name = format_ident!("{eff_name}Injs");
-    let eff = arms
-        .iter()
-        .map(|HandlerArm { eff, .. }| format_ident!("__{eff}"));
-    let arg_name = arms
+    quote!(use #mod_name::{#eff_name, #injs_name};)
+}
+
+/// A match on `eff` that runs the handler arms. With `..`, the result is wrapped in `Ok`, and
+/// operations without an arm are given back with `Err`.
+fn quote_group_match(mod_name: &Ident, eff_name: &Ident, arms: &Arms) -> proc_macro2::TokenStream {
+    let injs_name = format_ident!("{eff_name}Injs");
+    let (variant, (arg_name, breaker)): (Vec<_>, (Vec<_>, Vec<_>)) = arms
+        .arms
         .iter()
-        .map(|HandlerArm { args, .. }| args.iter().collect::<Vec<_>>());
-    let breaker = arms.iter().map(|HandlerArm { breaker, .. }| breaker);
+        .map(|HandlerArm { eff, args, breaker }| {
+            let arg_name = args.iter().collect::<Vec<_>>();
+            (format_ident!("__{eff}"), (arg_name, breaker))
+        })
+        .unzip();
+    let handled = |flow| match arms.forward {
+        Some(_) => quote!(::core::result::Result::Ok(#flow)),
+        None => flow,
+    };
+    let arms_flow = variant.iter().map(|variant| {
+        handled(quote! {
+            match flow {
+                ::core::ops::ControlFlow::Continue(inj) =>
+                    ::core::ops::ControlFlow::Continue(#injs_name::#variant(inj)),
+                ::core::ops::ControlFlow::Break(ret) => ::core::ops::ControlFlow::Break(ret),
+            }
+        })
+    });
+    // a `_` arm doesn't know which operation it has, so it can only break out of the computation
+    let wildcard = arms.wildcard.as_ref().map(|(underscore, breaker)| {
+        let flow = handled(quote! {
+            match flow {
+                ::core::ops::ControlFlow::Continue(never) => match never {},
+                ::core::ops::ControlFlow::Break(ret) => ::core::ops::ControlFlow::Break(ret),
+            }
+        });
+        quote! {
+            #underscore => {
+                let flow: ::core::ops::ControlFlow<_, ::core::convert::Infallible> = #breaker;
+                #flow
+            }
+        }
+    });
+    let forward = arms
+        .forward
+        .map(|_| quote!(eff => ::core::result::Result::Err(eff)));
+    // without `_` or `..`, every operation needs an arm. the match can't name the ones that are
+    // missing, so they're checked against the group's list of operations instead
+    let check = (arms.wildcard.is_none() && arms.forward.is_none()).then(|| {
+        let group = eff_name.to_string();
+        let handled = arms.arms.iter().map(|arm| arm.eff.to_string());
+        let check = quote_spanned! {eff_name.span()=>
+            const _: () = if let ::core::option::Option::Some(missing) =
+                ::effing_mad::macro_impl::MissingOperations::new(
+                    #group,
+                    #mod_name::__OPERATIONS,
+                    &[#(#handled),*],
+                )
+                .message()
+            {
+                ::core::panic!("{}", missing)
+            };
+        };
+        (
+            check,
+            quote!(#[allow(unreachable_patterns)] _ => ::core::unreachable!()),
+        )
+    });
+    let (check, exhaustive) = check.unzip();
     quote! {
         {
-            use #mod_name::*;
-            #moveness |eff: #eff_name #generics| #asyncness {
-                match eff {
-                    #(
-                    #eff_name::#eff(#(#arg_name),*) => match #breaker {
-                        ::core::ops::ControlFlow::Continue(inj) =>
-                            ::core::ops::ControlFlow::Continue(#injs_name::#eff(inj)),
-                        ::core::ops::ControlFlow::Break(ret) => ::core::ops::ControlFlow::Break(ret),
+            #check
+            match eff {
+                #(
+                #eff_name::#variant(#(#arg_name),*) => {
+                    let flow = #breaker;
+                    #arms_flow
+                }
+                )*
+                #wildcard
+                #forward
+                #exhaustive
+            }
+        }
+    }
+}
+
+#[proc_macro]
+pub fn handler(input: TokenStream) -> TokenStream {
+    match parse_macro_input!(input as Handler) {
+        Handler::Group {
+            asyncness,
+            moveness,
+            name:
+                GroupName {
+                    mod_name,
+                    eff_name,
+                    generics,
+                },
+            arms,
+        } => {
+            let matcher = quote_group_match(&mod_name, &eff_name, &arms);
+            let use_group = quote_use_group(&mod_name, &eff_name);
+            // the future outlives the closure call, so it has to take the effect with it
+            let asyncness = asyncness.map(|asyncness| quote!(#asyncness move));
+            let on_effect = quote! {
+                #moveness |eff: #eff_name #generics| #asyncness {
+                    #matcher
+                }
+            };
+            // with `..`, the closure gives back a `Result` for `handle_partial` instead. with a
+            // return arm, the handler becomes a pair of closures to pass to `handle_with_return`
+            let handler = match arms.return_arm {
+                Some(ReturnArm {
+                    ret,
+                    output: Some(output),
+                    body,
+                }) => quote! {
+                    (#on_effect, #moveness |#ret| -> #output { #body })
+                },
+                Some(ReturnArm {
+                    ret,
+                    output: None,
+                    body,
+                }) => quote! {
+                    (#on_effect, #moveness |#ret| #body)
+                },
+                None => on_effect,
+            };
+            quote! {
+                {
+                    #use_group
+                    #handler
+                }
+            }
+        }
+        Handler::Struct {
+            vis,
+            name,
+            generics,
+            fields,
+            group:
+                GroupName {
+                    mod_name,
+                    eff_name,
+                    generics: eff_generics,
+                },
+            arms,
+        } => {
+            // `R` is the return type of the computation being handled
+            let mut impl_generics = generics.clone();
+            impl_generics.params.push(parse_quote!(R));
+            let (impl_generics, _, _) = impl_generics.split_for_impl();
+            let (_, ty_generics, where_clause) = generics.split_for_impl();
+            let eff_ty = quote!(#mod_name::#eff_name #eff_generics);
+            let matcher = quote_group_match(&mod_name, &eff_name, &arms);
+            let use_group = quote_use_group(&mod_name, &eff_name);
+            let (output, on_return) =
+                match arms.return_arm {
+                    Some(ReturnArm {
+                        ret,
+                        output: Some(output),
+                        body,
+                    }) => (output.to_token_stream(), quote!(let #ret = ret; #body)),
+                    Some(ReturnArm {
+                        ret, output: None, ..
+                    }) => return Error::new_spanned(
+                        ret,
+                        "return arms in handler structs need a return type, e.g. `return(v) -> T`",
+                    )
+                    .into_compile_error()
+                    .into(),
+                    None => (quote!(R), quote!(ret)),
+                };
+            quote! {
+                #vis struct #name #generics #where_clause #fields
+
+                impl #impl_generics ::effing_mad::Handler<#eff_ty, R> for #name #ty_generics
+                #where_clause
+                {
+                    type Output = #output;
+
+                    fn handle(
+                        &mut self,
+                        eff: #eff_ty,
+                    ) -> ::core::ops::ControlFlow<
+                        Self::Output,
+                        <#eff_ty as ::effing_mad::Effect>::Injection,
+                    > {
+                        #use_group
+                        #matcher
+                    }
+
+                    fn on_return(&mut self, ret: R) -> Self::Output {
+                        #on_return
+                    }
+                }
+            }
+        }
+        Handler::Many {
+            moveness,
+            state,
+            items,
+        } => {
+            let (state_pat, state_ty) = match state {
+                Some((pat, ty)) => (pat.to_token_stream(), ty.to_token_stream()),
+                None => (quote!(_), quote!(&mut ())),
+            };
+            let handlers = items.iter().map(|item| match item {
+                ManyItem::Group {
+                    name:
+                        GroupName {
+                            mod_name,
+                            eff_name,
+                            generics,
+                        },
+                    arms,
+                } => {
+                    let matcher = quote_group_match(mod_name, eff_name, arms);
+                    let use_group = quote_use_group(mod_name, eff_name);
+                    quote! {
+                        {
+                            #use_group
+                            #moveness |#state_pat: #state_ty, eff: #eff_name #generics| {
+                                #matcher
+                            }
+                        }
                     }
-                    ),*
                 }
+                ManyItem::Single { eff, breaker } => quote! {
+                    #moveness |#state_pat: #state_ty, #eff| #breaker
+                },
+            });
+            quote! {
+                ::effing_mad::frunk::hlist![#(#handlers),*]
             }
         }
     }
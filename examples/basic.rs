@@ -1,6 +1,7 @@
 //! This program prints out the following:
 //! log: I know your password! It's monadtransformerssuck
 //! log: I'm going to do evil things and you can't stop me!
+//! log: also, monadtransformerssuck is a terrible password
 //! log: starting...
 //! log: something went wrong! aah!
 //!
@@ -11,16 +12,18 @@
 
 #![feature(generators)]
 #![feature(generator_trait)]
+#![feature(pin_macro)]
+#![feature(generic_associated_types)]
 
 use core::ops::ControlFlow;
 
-use effing_mad::{effectful, handle, Effect};
+use effing_mad::{effectful, handle, Effect, Lend, Lent};
 
 fn main() {
     let cancelled = handle(combined(), |Cancel| ControlFlow::Break(()));
 
-    let logged = handle(cancelled, |Log(msg)| {
-        println!("log: {msg}");
+    let logged = handle(cancelled, |log: Lent<Log<'static>>| {
+        log.with(|Log(msg)| println!("log: {msg}"));
         ControlFlow::Continue(())
     });
 
@@ -39,13 +42,19 @@ impl Effect for Cancel {
     type Injection = effing_mad::Never;
 }
 
-struct Log<'a>(std::borrow::Cow<'a, str>);
+struct Log<'a>(&'a str);
 
 impl<'a> Effect for Log<'a> {
     /// The logging handler does not provide any information back to the effectful function.
     type Injection = ();
 }
 
+/// Log messages only need to live until the handler has printed them, so they can borrow from the
+/// function logging them.
+impl<'a> Lend for Log<'a> {
+    type At<'b> = Log<'b>;
+}
+
 struct FileRead(String);
 
 impl Effect for FileRead {
@@ -60,24 +69,29 @@ impl Effect for FileRead {
 // execution, so this function would otherwise cause a warning. However, this warning only comes up
 // if there is a `yield` after cancelling, not if there are only normal statements and expressions.
 #[allow(unreachable_code)]
-#[effectful(Cancel, Log<'a>)]
-fn simple<'a>() {
-    yield Log("starting...".into());
-    yield Log("something went wrong! aah!".into());
+#[effectful(Cancel, Lent<Log<'static>>)]
+fn simple() {
+    yield Log("starting...");
+    yield Log("something went wrong! aah!");
     yield Cancel;
-    yield Log("no, sorry. i have gone home.".into());
+    yield Log("no, sorry. i have gone home.");
 }
 
 // This function demonstrates how effect handlers can pass values back into the effectful function,
 // and how the `do_` operator can be used to call effectful functions, as long as the callee has a
 // subset of the caller's effects.
-#[effectful(Cancel, Log<'a>, FileRead)]
-fn combined<'a>() {
+// Since Log is lent rather than given to the handler, it can borrow from locals of the function.
+#[effectful(Cancel, Lent<Log<'static>>, FileRead)]
+fn combined() {
     let mischief = yield FileRead("~/my passwords.txt".into());
-    // this is why Log has to use Cow - we can't yield something referencing local content...
-    // ...for some reason. I sure hope that doesn't foil my plans to take over Rust with algebraic
-    // effects.
-    yield Log(format!("I know your password! It's {mischief}").into());
-    yield Log("I'm going to do evil things and you can't stop me!".into());
+    // effectful functions can hold on to borrows of their own locals across yields...
+    let password = mischief.trim();
+    let gloat = format!("I know your password! It's {password}");
+    yield Log(&gloat);
+    yield Log("I'm going to do evil things and you can't stop me!");
+    // ...and yield effects that borrow them too. I sure hope the handler doesn't keep hold of this
+    // one, or else my plans to take over Rust with algebraic effects would be foiled.
+    let insult = format!("also, {password} is a terrible password");
+    yield Log(&insult);
     simple().do_;
 }
@@ -0,0 +1,66 @@
+//! A demonstration of the #[effect] attribute, which defines an effect group with a trait instead
+//! of the effects! macro. As well as the group itself, it generates a handler trait with a method
+//! for each operation. Operations with a body get a default implementation in the handler trait.
+//! Here the same program is run with a handler that reads from stdin, and with one that reads
+//! from a script.
+
+#![feature(generators)]
+#![feature(generator_trait)]
+
+use std::io::BufRead;
+
+use effing_mad::{effect, effectful, handle, run, Handled};
+
+fn main() {
+    let scripted = Script {
+        lines: vec!["Ferris".into(), "crab".into()],
+    };
+    run(handle(greet(), Handled(scripted)));
+
+    println!("Now it's your turn!");
+    run(handle(greet(), Handled(Stdio)));
+}
+
+/// Talking to the user
+#[effect]
+trait Console {
+    /// Get a line of input from the user.
+    fn read_line() -> String;
+    /// Show some text to the user.
+    fn print(text: String) {
+        println!("{text}");
+    }
+}
+
+struct Stdio;
+
+impl ConsoleHandler for Stdio {
+    fn read_line(&mut self) -> String {
+        let mut line = String::new();
+        std::io::stdin().lock().read_line(&mut line).unwrap();
+        line.trim_end().to_string()
+    }
+}
+
+struct Script {
+    lines: Vec<String>,
+}
+
+impl ConsoleHandler for Script {
+    fn read_line(&mut self) -> String {
+        let line = self.lines.remove(0);
+        println!("> {line}");
+        line
+    }
+}
+
+#[effectful(Console)]
+fn greet() {
+    yield Console::print("What's your name?".into());
+    let name = yield Console::read_line();
+    yield Console::print("What's your favourite animal?".into());
+    let animal = yield Console::read_line();
+    yield Console::print(format!(
+        "Hello {name}, your favourite animal is the {animal}"
+    ));
+}
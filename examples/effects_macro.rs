@@ -31,7 +31,7 @@ fn main() {
 effing_mad::effects! {
     state::State<T> {
         fn get() -> T;
-        fn put(v: T) -> ();
+        fn put(v: T);
     }
 }
 
@@ -0,0 +1,74 @@
+//! A demonstration of handling several effects in one go with `handle_many`. All of the handlers
+//! share some state - here, a buffer of log messages - which would not be possible if each effect
+//! was handled by its own call to `handle`. The messages are only printed if the work is cancelled.
+//!
+//! This program prints out the following:
+//! cancelled! here's what happened before that:
+//!   starting...
+//!   50% done
+//!   something went wrong! aah!
+
+#![feature(generators)]
+#![feature(generator_trait)]
+
+use core::ops::ControlFlow;
+
+use effing_mad::{effectful, handle_many, handler, run, Effect};
+
+fn main() {
+    let handled = handle_many(
+        work(),
+        Vec::new(),
+        handler! {
+            |log: &mut Vec<String>|
+            Log(msg) => {
+                log.push(msg);
+                ControlFlow::Continue(())
+            },
+            Cancel => {
+                println!("cancelled! here's what happened before that:");
+                for msg in log.drain(..) {
+                    println!("  {msg}");
+                }
+                ControlFlow::Break(())
+            },
+            // effect groups from effects! can be handled alongside other effects
+            progress::Progress {
+                report(percent) => {
+                    log.push(format!("{percent}% done"));
+                    ControlFlow::Continue(())
+                },
+            },
+        },
+    );
+    run(handled);
+}
+
+struct Log(String);
+
+impl Effect for Log {
+    type Injection = ();
+}
+
+struct Cancel;
+
+impl Effect for Cancel {
+    type Injection = effing_mad::Never;
+}
+
+effing_mad::effects! {
+    progress::Progress {
+        fn report(percent: u8) -> ();
+    }
+}
+
+use progress::Progress;
+#[allow(unreachable_code)]
+#[effectful(Log, Cancel, Progress)]
+fn work() {
+    yield Log("starting...".into());
+    yield Progress::report(50);
+    yield Log("something went wrong! aah!".into());
+    yield Cancel;
+    yield Progress::report(100);
+}
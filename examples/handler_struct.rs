@@ -0,0 +1,83 @@
+//! A demonstration of named handlers. A handler can be any type implementing the `Handler` trait,
+//! which means it can be stored, passed around and reused like any other value. Handlers for
+//! effect groups can be generated by the handler! macro, while others are written by hand.
+//!
+//! This program prints out the following:
+//! log: counted up to 35
+//! log: counted up to 36
+//! log: counted up to 37
+//! count_up returned "done" with the counter at 37
+
+#![feature(generators)]
+#![feature(generator_trait)]
+
+use core::ops::ControlFlow;
+
+use effing_mad::{effectful, handle, handler, run, Effect, Handler};
+
+fn main() {
+    let handled = handle(count_up(3), Counter { value: 34 });
+    let handled = handle(handled, Logger { lines: Vec::new() });
+    let (ret, value) = run(handled);
+    println!("count_up returned {ret:?} with the counter at {value}");
+}
+
+effing_mad::effects! {
+    state::State<T> {
+        fn get() -> T;
+        fn put(v: T) -> ();
+    }
+}
+
+// The fields of the struct are accessible from the arms through `self`. In the return arm, `R` is
+// the return type of the function being handled.
+handler! {
+    struct Counter { value: i32 } for state::State<i32>,
+    get() => ControlFlow::Continue(self.value),
+    put(v) => {
+        self.value = v;
+        ControlFlow::Continue(())
+    },
+    return(ret) -> (R, i32) => (ret, self.value),
+}
+
+struct Log(String);
+
+impl Effect for Log {
+    type Injection = ();
+}
+
+/// Holds on to log messages and prints them all at once when it is finished with.
+struct Logger {
+    lines: Vec<String>,
+}
+
+impl<R> Handler<Log, R> for Logger {
+    type Output = R;
+
+    fn handle(&mut self, Log(line): Log) -> ControlFlow<R, ()> {
+        self.lines.push(line);
+        ControlFlow::Continue(())
+    }
+
+    fn on_return(&mut self, ret: R) -> R {
+        ret
+    }
+
+    fn on_drop(&mut self) {
+        for line in self.lines.drain(..) {
+            println!("log: {line}");
+        }
+    }
+}
+
+use state::State;
+#[effectful(State<i32>, Log)]
+fn count_up(times: usize) -> &'static str {
+    for _ in 0..times {
+        let value = yield State::get();
+        yield State::put(value + 1);
+        yield Log(format!("counted up to {}", value + 1));
+    }
+    "done"
+}
@@ -0,0 +1,60 @@
+//! This program prints out the following:
+//! log: fetching page 1
+//! log: fetching page 2
+//! log: fetching page 3
+//! log: fetching page 4
+//! the long pages are ["page 2 is long", "page 4 is long"]
+//!
+//! Like `Option::map` in functor_eff, `Iterator` methods can be made effectful with
+//! `into_effectful()`, after which they take closures that return generators instead of values.
+//! The resulting generator has the effects of those closures, which can be handled however the
+//! caller likes - here, synchronously.
+
+#![feature(generators)]
+#![feature(generator_trait)]
+
+use core::ops::ControlFlow;
+
+use effing_mad::{effectful, functor_eff::IntoEffectfulIter, handle, run, Effect};
+
+fn main() {
+    let pages = [1, 2, 3, 4]
+        .into_iter()
+        .into_effectful()
+        .map(fetch)
+        .filter(|page: &String| is_long(page.len()))
+        .collect::<Vec<_>>();
+    let fetched = handle(pages, |Fetch(n)| {
+        let page = if n % 2 == 0 { "is long" } else { "no" };
+        ControlFlow::Continue(format!("page {n} {page}"))
+    });
+    let logged = handle(fetched, |Log(msg)| {
+        println!("log: {msg}");
+        ControlFlow::Continue(())
+    });
+    println!("the long pages are {:?}", run(logged));
+}
+
+struct Fetch(u32);
+
+impl Effect for Fetch {
+    type Injection = String;
+}
+
+struct Log(String);
+
+impl Effect for Log {
+    type Injection = ();
+}
+
+#[effectful(Fetch, Log)]
+fn fetch(n: u32) -> String {
+    yield Log(format!("fetching page {n}"));
+    yield Fetch(n)
+}
+
+// `filter` passes its closure a reference, which the generator can't hold on to
+#[effectful(Fetch, Log)]
+fn is_long(len: usize) -> bool {
+    len > 10
+}
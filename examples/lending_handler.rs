@@ -0,0 +1,50 @@
+//! A demonstration of lending handlers, whose injections borrow from the handler itself. Here the
+//! handler owns a large buffer, and an effectful function works on it in place through the
+//! `&mut [u8]` it is lent, instead of getting a copy of the whole buffer every time it asks.
+//!
+//! This program prints out the following:
+//! checksum: 130816
+//! first bytes: [0, 2, 4, 6]
+
+#![feature(generators)]
+#![feature(generator_trait)]
+#![feature(generic_associated_types)]
+
+use core::ops::ControlFlow;
+
+use effing_mad::{effectful, handle_lending, run, Borrowed, Effect, LendInjection};
+
+fn main() {
+    let mut buffer = vec![0; 1 << 20];
+    let handled = handle_lending(
+        double_and_sum(),
+        Buffer::lend_from(&mut buffer, |buffer, Buffer| {
+            ControlFlow::Continue(&mut buffer[..])
+        }),
+    );
+    println!("checksum: {}", run(handled));
+    println!("first bytes: {:?}", &buffer[..4]);
+}
+
+struct Buffer;
+
+impl Effect for Buffer {
+    /// The buffer is lent out by the handler, so the injection has to be `Borrowed`.
+    type Injection = Borrowed<Self>;
+}
+
+impl LendInjection for Buffer {
+    type At<'h> = &'h mut [u8];
+}
+
+// The buffer can only be looked at inside `with`, since the borrow ends as soon as the function
+// yields again. If it tried to hold on to the `Borrowed` across a yield, the program would abort.
+#[effectful(Buffer)]
+fn double_and_sum() -> u64 {
+    (yield Buffer).with(|buffer| {
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            *byte = (i as u8).wrapping_mul(2);
+        }
+    });
+    (yield Buffer).with(|buffer| buffer[..512].iter().map(|&byte| u64::from(byte)).sum())
+}
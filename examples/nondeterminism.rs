@@ -0,0 +1,42 @@
+//! A demonstration of multi-shot handlers, which can resume an effectful function more than once.
+//! Here the `Choose` effect picks a number from a range, and its handler tries every number in the
+//! range, collecting the results of all of the branches. This is how nondeterminism is modelled
+//! with algebraic effects, and it means `pythagorean_triples` can be written as if it is only
+//! looking for one solution.
+
+#![feature(generators)]
+#![feature(generator_trait)]
+#![feature(generator_clone)]
+
+use core::ops::RangeInclusive;
+
+use effing_mad::{effectful, handle_multishot, Effect};
+
+fn main() {
+    let triples = handle_multishot(pythagorean_triples(20), |Choose(range), k| {
+        range.flat_map(|n| k.resume(n)).collect()
+    });
+    for (a, b, c) in triples {
+        println!("{a}² + {b}² = {c}²");
+    }
+}
+
+struct Choose(RangeInclusive<u32>);
+
+impl Effect for Choose {
+    /// The number that was chosen from the range.
+    type Injection = u32;
+}
+
+// Multi-shot handlers need to make copies of the suspended function, hence the `Clone` bound.
+#[effectful(Choose; Clone)]
+fn pythagorean_triples(max: u32) -> Vec<(u32, u32, u32)> {
+    let a = yield Choose(1..=max);
+    let b = yield Choose(a..=max);
+    let c = yield Choose(b..=max);
+    if a * a + b * b == c * c {
+        vec![(a, b, c)]
+    } else {
+        vec![]
+    }
+}
@@ -0,0 +1,43 @@
+//! A demonstration of return clauses, which let a handler decide what the handled computation
+//! returns. Here the `Failure` handler turns a function that returns an `i32` (or bails out) into
+//! one that returns a `Result<i32, String>`, without the function itself knowing about `Result`.
+
+#![feature(generators)]
+#![feature(generator_trait)]
+
+use core::ops::ControlFlow;
+use effing_mad::{effectful, handle_with_return, handler, run};
+
+fn main() {
+    for input in ["1 2 3", "4 five 6"] {
+        let (on_effect, on_return) = handler! {
+            failure::Failure,
+            fail(msg) => ControlFlow::Break(Err(msg)),
+            return(total) => Ok(total),
+        };
+        let handled = handle_with_return(sum(input), on_effect, on_return);
+        match run(handled) {
+            Ok(total) => println!("{input:?} adds up to {total}"),
+            Err(msg) => println!("{input:?} can't be added up: {msg}"),
+        }
+    }
+}
+
+effing_mad::effects! {
+    failure::Failure {
+        fn fail(msg: String) -> effing_mad::Never;
+    }
+}
+
+use failure::Failure;
+#[effectful(Failure)]
+fn sum(input: &str) -> i32 {
+    let mut total = 0;
+    for word in input.split_whitespace() {
+        match word.parse::<i32>() {
+            Ok(n) => total += n,
+            Err(_) => match yield Failure::fail(format!("{word:?} is not a number")) {},
+        }
+    }
+    total
+}
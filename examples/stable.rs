@@ -0,0 +1,72 @@
+//! This program prints out the following:
+//! log: adding up the numbers
+//! log: 1 + 2 + 3 = 6
+//! result: Ok(6)
+//! result: Err(ParseIntError { kind: InvalidDigit })
+//!
+//! Unlike the other examples, this one doesn't need any unstable features, so it builds on stable
+//! Rust with `--no-default-features`. Without generators, effects are yielded with `yield_!(...)`
+//! instead of `yield ...`, and everything else works the same way. Code generated for the nightly
+//! backend still needs generators though, so crates that support both have to enable them
+//! conditionally.
+
+#![cfg_attr(feature = "nightly", feature(generators))]
+#![cfg_attr(feature = "nightly", feature(generator_trait))]
+#![cfg_attr(feature = "nightly", feature(pin_macro))]
+
+use core::{num::ParseIntError, ops::ControlFlow};
+
+use effing_mad::{
+    effectful, handle, run,
+    std_effects::{catch, Throw},
+    transform1, Effect,
+};
+
+fn main() {
+    let inputs: [&[&str]; 2] = [&["1", "2", "3"], &["4", "five"]];
+    for numbers in inputs {
+        let summed = sum(numbers);
+        // Log, Throw -> Print, Throw
+        let printed = transform1(summed, print_log);
+        let handled = handle(printed, |Print(line)| {
+            println!("{line}");
+            ControlFlow::Continue(())
+        });
+        println!("result: {:?}", run(catch(handled)));
+    }
+}
+
+struct Print(String);
+
+impl Effect for Print {
+    type Injection = ();
+}
+
+struct Log(String);
+
+impl Effect for Log {
+    type Injection = ();
+}
+
+#[effectful(Log)]
+fn announce(numbers: &[&str]) {
+    if numbers.len() > 2 {
+        yield_!(Log("adding up the numbers".into()));
+    }
+}
+
+#[effectful(Log, Throw<ParseIntError>)]
+fn sum(numbers: &[&str]) -> i32 {
+    announce(numbers).do_;
+    let mut total = 0;
+    for number in numbers {
+        total += number.parse::<i32>()?;
+    }
+    yield_!(Log(format!("{} = {total}", numbers.join(" + "))));
+    total
+}
+
+#[effectful(Print)]
+fn print_log(Log(message): Log) {
+    yield_!(Print(format!("log: {message}")));
+}
@@ -0,0 +1,137 @@
+//! A tour of the effects and handlers in `effing_mad::std_effects`. Each handler is checked
+//! against the result it should give, so this example doubles as a test of them.
+
+#![feature(generators)]
+#![feature(generator_trait)]
+#![feature(generator_clone)]
+
+use effing_mad::{
+    effectful, run,
+    std_effects::{
+        catch, run_cancel, run_choose, run_fresh, run_random, run_reader, run_state, run_writer,
+        Cancel, Choose, Fresh, Random, Reader, State, Throw, Writer,
+    },
+};
+
+fn main() {
+    // a computation's result is returned alongside the final state
+    assert_eq!(run(run_state(increment(), 41)), ("incremented", 42));
+
+    assert_eq!(run(run_reader(greeting(), "world")), "hello, world!");
+
+    // the writer's output can be collected into any collection that can be extended
+    let ((), lines): ((), Vec<_>) = run(run_writer(countdown(3)));
+    assert_eq!(lines, ["3", "2", "1", "liftoff!"]);
+    let ((), text): ((), String) = run(run_writer(countdown(3)));
+    assert_eq!(text, "321liftoff!");
+
+    assert_eq!(run(catch(divide(12, 4))), Ok(3));
+    assert_eq!(run(catch(divide(12, 0))), Err("division by zero"));
+    assert_eq!(run(catch(parse_and_add("1", "2"))), Ok(3));
+    assert_eq!(
+        run(catch(parse_and_add("1", "two"))),
+        Err(ParseError("not a number"))
+    );
+
+    assert_eq!(run(run_cancel(maybe_cancel(false))), Some(1));
+    assert_eq!(run(run_cancel(maybe_cancel(true))), None);
+
+    let sums: Vec<_> = run_choose(two_coins());
+    assert_eq!(sums, [2, 1, 1, 0]);
+
+    assert_eq!(run(run_fresh(three_ids())), [0, 1, 2]);
+
+    // the same seed gives the same numbers
+    let a = run(run_random(two_dice(), 1234));
+    let b = run(run_random(two_dice(), 1234));
+    assert_eq!(a, b);
+    assert!((1..=6).contains(&a.0) && (1..=6).contains(&a.1));
+
+    // handlers can be combined to handle several standard effects
+    let handled = catch(run_state(checked_decrement(), 1));
+    assert_eq!(run(handled), Ok(((), 0)));
+    let handled = catch(run_state(checked_decrement(), 0));
+    assert_eq!(run(handled), Err("can't go below zero"));
+
+    println!("all standard effects behaved as expected");
+}
+
+#[effectful(State<i32>)]
+fn increment() -> &'static str {
+    let n = yield State::get();
+    yield State::put(n + 1);
+    "incremented"
+}
+
+#[effectful(Reader<&'static str>)]
+fn greeting() -> String {
+    let name = yield Reader::ask();
+    format!("hello, {name}!")
+}
+
+#[effectful(Writer<String>)]
+fn countdown(from: u32) {
+    for n in (1..=from).rev() {
+        yield Writer::tell(n.to_string());
+    }
+    yield Writer::tell("liftoff!".to_string());
+}
+
+#[effectful(Throw<&'static str>)]
+fn divide(a: i32, b: i32) -> i32 {
+    if b == 0 {
+        match yield Throw("division by zero") {}
+    }
+    a / b
+}
+
+// `?` throws errors as effects, converting them with `From` along the way
+#[effectful(Throw<ParseError>)]
+fn parse_and_add(a: &str, b: &str) -> i32 {
+    a.parse::<i32>()? + b.parse::<i32>()?
+}
+
+#[derive(Debug, PartialEq)]
+struct ParseError(&'static str);
+
+impl From<std::num::ParseIntError> for ParseError {
+    fn from(_: std::num::ParseIntError) -> Self {
+        ParseError("not a number")
+    }
+}
+
+#[effectful(Cancel)]
+fn maybe_cancel(cancel: bool) -> i32 {
+    if cancel {
+        yield Cancel;
+    }
+    1
+}
+
+#[effectful(Choose; Clone)]
+fn two_coins() -> u32 {
+    let a = yield Choose;
+    let b = yield Choose;
+    a as u32 + b as u32
+}
+
+#[effectful(Fresh)]
+fn three_ids() -> [u64; 3] {
+    [yield Fresh, yield Fresh, yield Fresh]
+}
+
+#[effectful(Random)]
+fn two_dice() -> (u64, u64) {
+    let a = yield Random;
+    let b = yield Random;
+    (a % 6 + 1, b % 6 + 1)
+}
+
+#[effectful(State<u32>, Throw<&'static str>)]
+fn checked_decrement() {
+    let n = yield State::get();
+    if n == 0 {
+        match yield Throw("can't go below zero") {}
+    }
+    yield State::put(n - 1);
+}
@@ -0,0 +1,79 @@
+//! This program prints out the following:
+//! log: looking up alice
+//! log: looking up bob
+//! total age: Some(79)
+//!
+//! The lookups are answered by another thread, which receives them over a channel. The effectful
+//! function doesn't know or care about this - as far as it's concerned, it's just yielding
+//! effects.
+
+#![feature(generators)]
+#![feature(generator_trait)]
+
+use std::{sync::mpsc, thread};
+
+use effing_mad::{
+    effectful,
+    frunk::{Coprod, Coproduct},
+    stream::{into_stream, Responder},
+    Effect,
+};
+use futures::{executor::block_on, StreamExt};
+
+fn main() {
+    let (requests, received) = mpsc::channel::<(Lookup, Responder<Coprod!(Log, Lookup)>)>();
+    let database = thread::spawn(move || {
+        for (Lookup(name), responder) in received {
+            let age = match name {
+                "alice" => Some(34),
+                "bob" => Some(45),
+                _ => None,
+            };
+            responder.respond::<Lookup, _>(age).unwrap();
+        }
+    });
+
+    let mut effects = into_stream(total_age(&["alice", "bob"]));
+    block_on(async {
+        while let Some((effs, responder)) = effects.next().await {
+            match effs {
+                Coproduct::Inl(Log(message)) => {
+                    println!("log: {message}");
+                    responder.respond::<Log, _>(()).unwrap();
+                }
+                Coproduct::Inr(Coproduct::Inl(lookup)) => {
+                    requests.send((lookup, responder)).unwrap()
+                }
+                Coproduct::Inr(Coproduct::Inr(never)) => match never {},
+            }
+        }
+    });
+    drop(requests);
+    database.join().unwrap();
+
+    println!("total age: {:?}", effects.into_return().unwrap());
+}
+
+struct Lookup(&'static str);
+
+impl Effect for Lookup {
+    type Injection = Option<u32>;
+}
+
+struct Log(String);
+
+impl Effect for Log {
+    type Injection = ();
+}
+
+// adds up everyone's ages, but only if they all have one
+#[effectful(Lookup, Log)]
+fn total_age(names: &'static [&'static str]) -> Option<u32> {
+    let mut total = Some(0);
+    for name in names {
+        yield Log(format!("looking up {name}"));
+        let age = yield Lookup(name);
+        total = total.zip(age).map(|(total, age)| total + age);
+    }
+    total
+}
@@ -3,59 +3,62 @@
 
 use std::ops::ControlFlow;
 
-use effing_mad::{effectful, handle, handler, run, run_async};
+use effing_mad::{effectful, handle, handler, run_async, run_blocking, Effect};
 
 fn main() {
-    let rt = tokio::runtime::Builder::new_current_thread()
+    // the same async handler is used from both async and sync code
+    let http = handler! {
+        async http::HttpRequest,
+        get(url) => {
+            let body = reqwest::get(url).await.unwrap().text().await.unwrap();
+            ControlFlow::Continue(body)
+        },
+    };
+
+    // the requests do their I/O on the runtime's own threads, whichever way they're run
+    let rt = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .build()
         .unwrap();
-    rt.block_on(interesting_and_useful());
 
-    boring_and_old_fashioned();
+    let (res1, res2) = rt.block_on(async {
+        let req1 = run_async(handle(example(), print_log), http);
+        let req2 = run_async(handle(example(), print_log), http);
+        // asyncified effectful functions can be composed in the same ways as traditional futures
+        futures::future::join(req1, req2).await
+    });
+    println!("asynchronously found {res1} and {res2} bytes");
+
+    let _rt = rt.enter();
+    let res = run_blocking(handle(example(), print_log), http);
+    println!("synchronously found {res} bytes");
 }
 
 // very complex and powerful API
 effing_mad::effects! {
-    HttpRequest {
+    http::HttpRequest {
         fn get(url: &'static str) -> String;
     }
 }
 
+use http::HttpRequest;
+
+struct Log(String);
+
+impl Effect for Log {
+    type Injection = ();
+}
+
 // this function does not specify whether the request happens synchronously or asynchronously
-#[effectful(HttpRequest)]
+#[effectful(HttpRequest, Log)]
 fn example() -> usize {
+    yield Log("fetching example.com".into());
     let body = yield HttpRequest::get("http://example.com");
     body.len()
 }
 
-async fn interesting_and_useful() {
-    let handler = handler! {
-        async HttpRequest,
-        get(url) => {
-            let body = reqwest::get(url).await.unwrap().text().await.unwrap();
-            ControlFlow::Continue(body)
-        },
-    };
-
-    let req1 = run_async(example(), handler);
-    let req2 = run_async(example(), handler);
-
-    // asyncified effectful functions can be composed in the same ways as traditional futures
-    let (res1, res2) = futures::future::join(req1, req2).await;
-    println!("asynchronously found {res1} and {res2} bytes");
-}
-
-fn boring_and_old_fashioned() {
-    let handler = handler! {
-        HttpRequest,
-        get(url) => {
-            let body = reqwest::blocking::get(url).unwrap().text().unwrap();
-            ControlFlow::Continue(body)
-        },
-    };
-
-    let req = handle(example(), handler);
-    let res = run(req);
-    println!("synchronously found {res} bytes");
+// logging is quick, so it doesn't need to be async even when requests are
+fn print_log(Log(message): Log) -> ControlFlow<usize, ()> {
+    println!("log: {message}");
+    ControlFlow::Continue(())
 }
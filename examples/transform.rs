@@ -3,13 +3,19 @@
 
 use core::ops::ControlFlow;
 
-use effing_mad::{effectful, handle, run, transform0, transform1, Effect};
+use effing_mad::{effectful, effectful_expr, handle, run, transform0, transform1, Effect};
 
 fn main() {
     let work = take_over_the_world();
     // Log, Lunchtime -> Print, Lunchtime
     // Introducing 1 new effect (Print) so must use transform1
-    let transformed = transform1(work, print_log);
+    // The handler can be written in place as an effectful closure
+    let transformed = transform1(
+        work,
+        effectful_expr!(Print => |Log(message, importance)| {
+            yield Print(format!("log (importance {importance}): {message}"));
+        }),
+    );
     // Print, Lunchtime -> Print
     // Not introducing new effects so must use transform0
     let transformed = transform0(transformed, print_lunchtime);
@@ -44,11 +50,6 @@ fn take_over_the_world() {
     yield Log("They're out of sausage rolls at the bakery!".into(), 100);
 }
 
-#[effectful(Print)]
-fn print_log(Log(message, importance): Log) {
-    yield Print(format!("log (importance {importance}): {message}"));
-}
-
 #[effectful(Print)]
 fn print_lunchtime(Lunchtime: Lunchtime) {
     yield Print("lunchtime: in progress...".into());
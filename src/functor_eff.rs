@@ -1,9 +1,9 @@
-use core::{
-    ops::{Generator, GeneratorState},
-    pin::Pin,
-};
+use core::{marker::PhantomData, pin::pin};
 
-use crate::injection::InjectionList;
+use crate::{
+    generator::{Generator, GeneratorState},
+    injection::InjectionList,
+};
 
 pub trait IntoEffectful: Sized {
     fn into_effectful(self) -> Effectful<Self> {
@@ -14,6 +14,17 @@ pub trait IntoEffectful: Sized {
 pub struct Effectful<T>(T);
 
 impl<T> IntoEffectful for Option<T> {}
+impl<T, E> IntoEffectful for Result<T, E> {}
+
+/// Iterators get their own version of [`IntoEffectful`], since a blanket impl of that for iterators
+/// would overlap with the ones for `Option` and `Result`.
+pub trait IntoEffectfulIter: Iterator + Sized {
+    fn into_effectful<Effs>(self) -> Effectful<Iter<Self, Effs>> {
+        Effectful(Iter(self, PhantomData))
+    }
+}
+
+impl<I: Iterator> IntoEffectfulIter for I {}
 
 impl<T> Effectful<Option<T>> {
     pub fn map<F, G, Effs, U>(
@@ -25,22 +36,20 @@ impl<T> Effectful<Option<T>> {
         G: Generator<Effs::List, Yield = Effs, Return = U>,
         Effs: InjectionList,
     {
-        move |mut injs| {
+        generator!(static move |mut injs: Effs::List| {
             match self.0 {
                 Some(v) => {
-                    let mut g = f(v);
+                    let mut g = pin!(f(v));
                     loop {
-                        // inside a generator, locals are pinned
-                        let pinned = unsafe { Pin::new_unchecked(&mut g) };
-                        match pinned.resume(injs) {
-                            GeneratorState::Yielded(effs) => injs = yield effs,
+                        match g.as_mut().resume(injs) {
+                            GeneratorState::Yielded(effs) => injs = yield_!(effs),
                             GeneratorState::Complete(v) => return Some(v),
                         }
                     }
                 }
                 None => return None,
             }
-        }
+        })
     }
 }
 
@@ -54,22 +63,20 @@ impl<T, E> Effectful<Result<T, E>> {
         G: Generator<Effs::List, Yield = Effs, Return = U>,
         Effs: InjectionList,
     {
-        move |mut injs| {
+        generator!(static move |mut injs: Effs::List| {
             match self.0 {
                 Ok(v) => {
-                    let mut g = f(v);
+                    let mut g = pin!(f(v));
                     loop {
-                        // inside a generator, locals are pinned
-                        let pinned = unsafe { Pin::new_unchecked(&mut g) };
-                        match pinned.resume(injs) {
-                            GeneratorState::Yielded(effs) => injs = yield effs,
+                        match g.as_mut().resume(injs) {
+                            GeneratorState::Yielded(effs) => injs = yield_!(effs),
                             GeneratorState::Complete(v) => return Ok(v),
                         }
                     }
                 }
                 Err(e) => return Err(e),
             }
-        }
+        })
     }
 
     pub fn map_err<O, G, Effs, F>(
@@ -81,21 +88,269 @@ impl<T, E> Effectful<Result<T, E>> {
         G: Generator<Effs::List, Yield = Effs, Return = F>,
         Effs: InjectionList,
     {
-        move |mut injs| {
+        generator!(static move |mut injs: Effs::List| {
             match self.0 {
                 Ok(v) => return Ok(v),
                 Err(e) => {
-                    let mut g = op(e);
+                    let mut g = pin!(op(e));
                     loop {
-                        // inside a generator, locals are pinned
-                        let pinned = unsafe { Pin::new_unchecked(&mut g) };
-                        match pinned.resume(injs) {
-                            GeneratorState::Yielded(effs) => injs = yield effs,
+                        match g.as_mut().resume(injs) {
+                            GeneratorState::Yielded(effs) => injs = yield_!(effs),
                             GeneratorState::Complete(e) => return Err(e),
                         }
                     }
                 }
             }
-        }
+        })
+    }
+}
+
+/// An iterator whose items are computed by generators with effects `Effs`. The adapters and
+/// consumers for these are the methods on `Effectful<I>`, which mirror those of [`Iterator`] but
+/// take closures returning generators rather than values.
+pub trait EffectfulIterator {
+    type Item;
+    type Effs: InjectionList;
+
+    /// Compute the next item, or `None` if there are no more.
+    fn next(
+        &mut self,
+    ) -> impl Generator<
+        <Self::Effs as InjectionList>::List,
+        Yield = Self::Effs,
+        Return = Option<Self::Item>,
+    >;
+}
+
+/// A regular iterator, made into an effectful one with [`IntoEffectfulIter::into_effectful`].
+pub struct Iter<I, Effs>(I, PhantomData<fn() -> Effs>);
+
+impl<I: Iterator, Effs: InjectionList> EffectfulIterator for Iter<I, Effs> {
+    type Item = I::Item;
+    type Effs = Effs;
+
+    fn next(&mut self) -> impl Generator<Effs::List, Yield = Effs, Return = Option<I::Item>> {
+        generator!(static move |_begin: Effs::List| { self.0.next() })
+    }
+}
+
+/// An effectful iterator created by `Effectful::map`.
+pub struct Map<I, F> {
+    iter: I,
+    f: F,
+}
+
+impl<I, F, G, Effs, U> EffectfulIterator for Map<I, F>
+where
+    I: EffectfulIterator<Effs = Effs>,
+    F: FnMut(I::Item) -> G,
+    G: Generator<Effs::List, Yield = Effs, Return = U>,
+    Effs: InjectionList,
+{
+    type Item = U;
+    type Effs = Effs;
+
+    fn next(&mut self) -> impl Generator<Effs::List, Yield = Effs, Return = Option<U>> {
+        generator!(static move |_begin: Effs::List| {
+            match delegate!(self.iter.next()) {
+                Some(item) => Some(delegate!((self.f)(item))),
+                None => None,
+            }
+        })
+    }
+}
+
+/// An effectful iterator created by `Effectful::filter`.
+pub struct Filter<I, P> {
+    iter: I,
+    predicate: P,
+}
+
+impl<I, P, G, Effs> EffectfulIterator for Filter<I, P>
+where
+    I: EffectfulIterator<Effs = Effs>,
+    P: FnMut(&I::Item) -> G,
+    G: Generator<Effs::List, Yield = Effs, Return = bool>,
+    Effs: InjectionList,
+{
+    type Item = I::Item;
+    type Effs = Effs;
+
+    fn next(&mut self) -> impl Generator<Effs::List, Yield = Effs, Return = Option<I::Item>> {
+        generator!(static move |_begin: Effs::List| {
+            loop {
+                let item = delegate!(self.iter.next())?;
+                if delegate!((self.predicate)(&item)) {
+                    return Some(item);
+                }
+            }
+        })
+    }
+}
+
+impl<I: EffectfulIterator> Effectful<I> {
+    pub fn map<F, G, Effs, U>(self, f: F) -> Effectful<Map<I, F>>
+    where
+        I: EffectfulIterator<Effs = Effs>,
+        F: FnMut(I::Item) -> G,
+        G: Generator<Effs::List, Yield = Effs, Return = U>,
+        Effs: InjectionList,
+    {
+        Effectful(Map { iter: self.0, f })
+    }
+
+    pub fn filter<P, G, Effs>(self, predicate: P) -> Effectful<Filter<I, P>>
+    where
+        I: EffectfulIterator<Effs = Effs>,
+        P: FnMut(&I::Item) -> G,
+        G: Generator<Effs::List, Yield = Effs, Return = bool>,
+        Effs: InjectionList,
+    {
+        Effectful(Filter {
+            iter: self.0,
+            predicate,
+        })
+    }
+
+    pub fn for_each<F, G, Effs>(
+        self,
+        mut f: F,
+    ) -> impl Generator<Effs::List, Yield = Effs, Return = ()>
+    where
+        I: EffectfulIterator<Effs = Effs>,
+        F: FnMut(I::Item) -> G,
+        G: Generator<Effs::List, Yield = Effs, Return = ()>,
+        Effs: InjectionList,
+    {
+        let mut iter = self.0;
+        generator!(static move |_begin: Effs::List| {
+            while let Some(item) = delegate!(iter.next()) {
+                delegate!(f(item));
+            }
+        })
+    }
+
+    pub fn fold<B, F, G, Effs>(
+        self,
+        init: B,
+        mut f: F,
+    ) -> impl Generator<Effs::List, Yield = Effs, Return = B>
+    where
+        I: EffectfulIterator<Effs = Effs>,
+        F: FnMut(B, I::Item) -> G,
+        G: Generator<Effs::List, Yield = Effs, Return = B>,
+        Effs: InjectionList,
+    {
+        let mut iter = self.0;
+        generator!(static move |_begin: Effs::List| {
+            let mut acc = init;
+            while let Some(item) = delegate!(iter.next()) {
+                acc = delegate!(f(acc, item));
+            }
+            acc
+        })
+    }
+
+    /// Like `fold`, but stops at the first error `f` returns.
+    pub fn try_fold<B, E, F, G, Effs>(
+        self,
+        init: B,
+        mut f: F,
+    ) -> impl Generator<Effs::List, Yield = Effs, Return = Result<B, E>>
+    where
+        I: EffectfulIterator<Effs = Effs>,
+        F: FnMut(B, I::Item) -> G,
+        G: Generator<Effs::List, Yield = Effs, Return = Result<B, E>>,
+        Effs: InjectionList,
+    {
+        let mut iter = self.0;
+        generator!(static move |_begin: Effs::List| {
+            let mut acc = init;
+            while let Some(item) = delegate!(iter.next()) {
+                acc = delegate!(f(acc, item))?;
+            }
+            Ok(acc)
+        })
+    }
+
+    pub fn find<P, G, Effs>(
+        self,
+        mut predicate: P,
+    ) -> impl Generator<Effs::List, Yield = Effs, Return = Option<I::Item>>
+    where
+        I: EffectfulIterator<Effs = Effs>,
+        P: FnMut(&I::Item) -> G,
+        G: Generator<Effs::List, Yield = Effs, Return = bool>,
+        Effs: InjectionList,
+    {
+        let mut iter = self.0;
+        generator!(static move |_begin: Effs::List| {
+            while let Some(item) = delegate!(iter.next()) {
+                if delegate!(predicate(&item)) {
+                    return Some(item);
+                }
+            }
+            None
+        })
+    }
+
+    pub fn any<F, G, Effs>(
+        self,
+        mut f: F,
+    ) -> impl Generator<Effs::List, Yield = Effs, Return = bool>
+    where
+        I: EffectfulIterator<Effs = Effs>,
+        F: FnMut(I::Item) -> G,
+        G: Generator<Effs::List, Yield = Effs, Return = bool>,
+        Effs: InjectionList,
+    {
+        let mut iter = self.0;
+        generator!(static move |_begin: Effs::List| {
+            while let Some(item) = delegate!(iter.next()) {
+                if delegate!(f(item)) {
+                    return true;
+                }
+            }
+            false
+        })
+    }
+
+    pub fn all<F, G, Effs>(
+        self,
+        mut f: F,
+    ) -> impl Generator<Effs::List, Yield = Effs, Return = bool>
+    where
+        I: EffectfulIterator<Effs = Effs>,
+        F: FnMut(I::Item) -> G,
+        G: Generator<Effs::List, Yield = Effs, Return = bool>,
+        Effs: InjectionList,
+    {
+        let mut iter = self.0;
+        generator!(static move |_begin: Effs::List| {
+            while let Some(item) = delegate!(iter.next()) {
+                if !delegate!(f(item)) {
+                    return false;
+                }
+            }
+            true
+        })
+    }
+
+    /// Collect the items into any collection that can be extended one item at a time, since
+    /// `FromIterator` would need all the items up front.
+    pub fn collect<C>(
+        self,
+    ) -> impl Generator<<I::Effs as InjectionList>::List, Yield = I::Effs, Return = C>
+    where
+        C: Default + Extend<I::Item>,
+    {
+        let mut iter = self.0;
+        generator!(static move |_begin: <I::Effs as InjectionList>::List| {
+            let mut collection = C::default();
+            while let Some(item) = delegate!(iter.next()) {
+                collection.extend(Some(item));
+            }
+            collection
+        })
     }
 }
@@ -0,0 +1,236 @@
+//! The generator trait that effectful computations implement.
+//!
+//! With the `nightly` feature (on by default) this is just `core::ops::Generator`, and effectful
+//! functions compile to real generators. Without it, effing-mad works on stable Rust: the trait
+//! is defined here instead, and effectful functions compile to `async` blocks wrapped in [`Gen`],
+//! which suspend by awaiting [`Co::yield_`]. Either way, code that names the trait through this
+//! module works with both backends.
+
+#[cfg(feature = "nightly")]
+pub use core::ops::{Generator, GeneratorState};
+
+#[cfg(not(feature = "nightly"))]
+pub use self::stable::*;
+
+/// Builds a generator from a closure-like body, using the nightly or stable backend as
+/// appropriate. Inside the body, `yield_!(value)` yields `value` and evaluates to the argument
+/// the generator is next resumed with. The generator is static, so it can pin things to its own
+/// stack with `pin!`.
+#[cfg(feature = "nightly")]
+macro_rules! generator {
+    (static move |mut $arg:ident: $arg_ty:ty| $body:block) => {
+        generator!(static move |$arg: $arg_ty| {
+            let mut $arg = $arg;
+            $body
+        })
+    };
+    (static move |$arg:ident: $arg_ty:ty| $body:block) => {{
+        #[allow(unused_macros)]
+        macro_rules! yield_ {
+            ($value:expr) => {
+                yield $value
+            };
+        }
+        static move |$arg: $arg_ty| $body
+    }};
+}
+
+#[cfg(not(feature = "nightly"))]
+macro_rules! generator {
+    (static move |mut $arg:ident: $arg_ty:ty| $body:block) => {
+        generator!(static move |$arg: $arg_ty| {
+            let mut $arg = $arg;
+            $body
+        })
+    };
+    (static move |$arg:ident: $arg_ty:ty| $body:block) => {{
+        // safety: `co` is hygienic, so it can only be used in this generator's body
+        let co = unsafe { $crate::generator::Co::<_, $arg_ty>::new() };
+        #[allow(unused_macros)]
+        macro_rules! yield_ {
+            ($value:expr) => {
+                co.yield_($value).await
+            };
+        }
+        $crate::generator::Gen::new(co, async move {
+            let $arg: $arg_ty = co.begin().await;
+            $body
+        })
+    }};
+}
+
+/// Inside the body of `generator!`, runs `gen` to completion and evaluates to its return value.
+/// `gen` must have the same effects as the generator it is run in, which passes them all on.
+macro_rules! delegate {
+    ($gen:expr) => {{
+        let mut gen = ::core::pin::pin!($gen);
+        let mut injs = $crate::frunk::coproduct::CoprodInjector::inject($crate::injection::Begin);
+        loop {
+            match $crate::generator::Generator::resume(gen.as_mut(), injs) {
+                $crate::generator::GeneratorState::Yielded(effs) => injs = yield_!(effs),
+                $crate::generator::GeneratorState::Complete(ret) => break ret,
+            }
+        }
+    }};
+}
+
+#[cfg(not(feature = "nightly"))]
+mod stable {
+    use core::{
+        cell::Cell,
+        future::Future,
+        marker::PhantomData,
+        pin::Pin,
+        ptr,
+        task::{Context, Poll},
+    };
+
+    /// The result of resuming a generator, mirroring `core::ops::GeneratorState`.
+    pub enum GeneratorState<Y, R> {
+        Yielded(Y),
+        Complete(R),
+    }
+
+    /// A computation that can be suspended and resumed, mirroring `core::ops::Generator`.
+    pub trait Generator<R = ()> {
+        type Yield;
+        type Return;
+
+        fn resume(self: Pin<&mut Self>, arg: R) -> GeneratorState<Self::Yield, Self::Return>;
+    }
+
+    impl<G: ?Sized + Generator<R>, R> Generator<R> for Pin<&mut G> {
+        type Yield = G::Yield;
+        type Return = G::Return;
+
+        fn resume(mut self: Pin<&mut Self>, arg: R) -> GeneratorState<G::Yield, G::Return> {
+            G::resume((*self).as_mut(), arg)
+        }
+    }
+
+    impl<G: ?Sized + Generator<R> + Unpin, R> Generator<R> for &mut G {
+        type Yield = G::Yield;
+        type Return = G::Return;
+
+        fn resume(mut self: Pin<&mut Self>, arg: R) -> GeneratorState<G::Yield, G::Return> {
+            G::resume(Pin::new(&mut *self), arg)
+        }
+    }
+
+    /// Where values are passed between a [`Gen`] and the future inside it.
+    enum Airlock<Y, A> {
+        Empty,
+        Yielded(Y),
+        Resumed(A),
+    }
+
+    /// A generator made from a future, which yields `Y` and is resumed with `A`. The future
+    /// suspends the generator by awaiting [`Co::yield_`].
+    pub struct Gen<Y, A, F> {
+        future: F,
+        _marker: PhantomData<fn(A) -> Y>,
+    }
+
+    impl<Y, A, F: Future> Gen<Y, A, F> {
+        pub fn new(_co: Co<Y, A>, future: F) -> Self {
+            Gen {
+                future,
+                _marker: PhantomData,
+            }
+        }
+    }
+
+    impl<Y, A, F: Future> Generator<A> for Gen<Y, A, F> {
+        type Yield = Y;
+        type Return = F::Output;
+
+        fn resume(self: Pin<&mut Self>, arg: A) -> GeneratorState<Y, F::Output> {
+            let airlock = Cell::new(Airlock::<Y, A>::Resumed(arg));
+            // wakers are only used to smuggle a pointer to the airlock into the future, so they
+            // don't need to do anything
+            let waker = crate::noop_waker(&airlock as *const _ as *const ());
+            // safety: `future` is never moved out of `self`, so it is structurally pinned
+            let future = unsafe { self.map_unchecked_mut(|gen| &mut gen.future) };
+            match future.poll(&mut Context::from_waker(&waker)) {
+                Poll::Ready(ret) => GeneratorState::Complete(ret),
+                Poll::Pending => match airlock.replace(Airlock::Empty) {
+                    Airlock::Yielded(value) => GeneratorState::Yielded(value),
+                    _ => panic!("effectful computations can only await effects"),
+                },
+            }
+        }
+    }
+
+    /// The handle a [`Gen`]'s future uses to talk to the generator around it.
+    pub struct Co<Y, A>(PhantomData<fn(A) -> Y>);
+
+    impl<Y, A> Clone for Co<Y, A> {
+        fn clone(&self) -> Self {
+            *self
+        }
+    }
+
+    impl<Y, A> Copy for Co<Y, A> {}
+
+    impl<Y, A> Co<Y, A> {
+        /// # Safety
+        /// The futures returned by this `Co` must only be awaited inside the future of the
+        /// [`Gen`] that it is passed to.
+        pub unsafe fn new() -> Self {
+            Co(PhantomData)
+        }
+
+        /// Suspend the generator, yielding `value`. Resolves to the argument the generator is
+        /// next resumed with.
+        pub fn yield_(self, value: Y) -> Yield<Y, A> {
+            Yield {
+                value: Some(value),
+                _marker: PhantomData,
+            }
+        }
+
+        /// Get the argument the generator was first resumed with. This must be awaited before
+        /// anything is yielded.
+        pub fn begin(self) -> Yield<Y, A> {
+            Yield {
+                value: None,
+                _marker: PhantomData,
+            }
+        }
+    }
+
+    /// The future returned by [`Co::yield_`] and [`Co::begin`].
+    pub struct Yield<Y, A> {
+        value: Option<Y>,
+        _marker: PhantomData<fn(A) -> Y>,
+    }
+
+    // the value is never pinned
+    impl<Y, A> Unpin for Yield<Y, A> {}
+
+    impl<Y, A> Future for Yield<Y, A> {
+        type Output = A;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<A> {
+            let waker = cx.waker();
+            // `block_on` uses the same waker, but without an airlock
+            assert!(
+                ptr::eq(waker.vtable(), &crate::NOOP_WAKER) && !waker.data().is_null(),
+                "effects can only be yielded inside an effectful computation",
+            );
+            // safety: the waker came from `Gen::resume`, whose airlock is still alive since it
+            // is polling us, and has the right type since `Co::new`'s contract was upheld
+            let airlock = unsafe { &*(waker.data() as *const Cell<Airlock<Y, A>>) };
+            match self.value.take() {
+                Some(value) => {
+                    airlock.set(Airlock::Yielded(value));
+                    Poll::Pending
+                }
+                None => match airlock.replace(Airlock::Empty) {
+                    Airlock::Resumed(arg) => Poll::Ready(arg),
+                    _ => unreachable!(),
+                },
+            }
+        }
+    }
+}
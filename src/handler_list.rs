@@ -0,0 +1,143 @@
+use core::{future::Future, marker::PhantomData, ops::ControlFlow};
+
+use frunk::{
+    coproduct::{CNil, CoprodInjector, CoprodUninjector},
+    HCons, HNil,
+};
+
+use crate::{injection::Tagged, Effect};
+
+/// An `HList` of handlers, one per effect, as taken by [`handle_many`](crate::handle_many). Each
+/// handler is a closure of the form `FnMut(&mut S, E) -> ControlFlow<R, E::Injection>`, where `S`
+/// is state shared between all the handlers in the list.
+///
+/// `Indices` is inferred, and records which effect each handler is for and where that effect and
+/// its injection live in `Effs` and `Injs`.
+pub trait HandlerList<S, Effs, Injs, R, Indices> {
+    /// The effects left over once all the handlers in this list have had their go.
+    type Remainder;
+
+    /// Pass the effect to whichever handler in this list handles it, or give it back (with a
+    /// narrower type) if none of them do.
+    fn handle(
+        &mut self,
+        state: &mut S,
+        effs: Effs,
+    ) -> Result<ControlFlow<R, Injs>, Self::Remainder>;
+}
+
+impl<S, Effs, Injs, R> HandlerList<S, Effs, Injs, R, HNil> for HNil {
+    type Remainder = Effs;
+
+    fn handle(&mut self, _state: &mut S, effs: Effs) -> Result<ControlFlow<R, Injs>, Effs> {
+        Err(effs)
+    }
+}
+
+impl<S, E, F, Tail, Effs, Injs, R, EffIndex, InjIndex, TailIndices>
+    HandlerList<S, Effs, Injs, R, HCons<PhantomData<(E, EffIndex, InjIndex)>, TailIndices>>
+    for HCons<F, Tail>
+where
+    E: Effect,
+    F: FnMut(&mut S, E) -> ControlFlow<R, E::Injection>,
+    Effs: CoprodUninjector<E, EffIndex>,
+    Injs: CoprodInjector<Tagged<E::Injection, E>, InjIndex>,
+    Tail: HandlerList<S, Effs::Remainder, Injs, R, TailIndices>,
+{
+    type Remainder = Tail::Remainder;
+
+    fn handle(
+        &mut self,
+        state: &mut S,
+        effs: Effs,
+    ) -> Result<ControlFlow<R, Injs>, Self::Remainder> {
+        match effs.uninject() {
+            Ok(eff) => Ok(match (self.head)(state, eff) {
+                ControlFlow::Continue(inj) => ControlFlow::Continue(Injs::inject(Tagged::new(inj))),
+                ControlFlow::Break(ret) => ControlFlow::Break(ret),
+            }),
+            Err(effs) => self.tail.handle(state, effs),
+        }
+    }
+}
+
+/// An `HList` of handlers that between them handle every effect in `Effs`, as taken by
+/// [`run_async_many`](crate::run_async_many). Each handler is either a closure of the form
+/// `FnMut(E) -> ControlFlow<R, E::Injection>`, or an async one of the form
+/// `FnMut(E) -> impl Future<Output = ControlFlow<R, E::Injection>>`. The two kinds can be mixed
+/// freely.
+///
+/// As with [`HandlerList`], `Indices` is inferred. It also records whether each handler is async.
+pub trait AsyncHandlerList<Effs, Injs, R, Indices> {
+    /// Pass the effect to whichever handler in this list handles it.
+    fn handle(&mut self, effs: Effs) -> impl Future<Output = ControlFlow<R, Injs>>;
+}
+
+impl<Injs, R> AsyncHandlerList<CNil, Injs, R, HNil> for HNil {
+    async fn handle(&mut self, effs: CNil) -> ControlFlow<R, Injs> {
+        match effs {}
+    }
+}
+
+// a synchronous handler
+impl<E, F, Tail, Effs, Injs, R, EffIndex, InjIndex, TailIndices>
+    AsyncHandlerList<Effs, Injs, R, HCons<PhantomData<(E, EffIndex, InjIndex)>, TailIndices>>
+    for HCons<F, Tail>
+where
+    E: Effect,
+    F: FnMut(E) -> ControlFlow<R, E::Injection>,
+    Effs: CoprodUninjector<E, EffIndex>,
+    Injs: CoprodInjector<Tagged<E::Injection, E>, InjIndex>,
+    Tail: AsyncHandlerList<Effs::Remainder, Injs, R, TailIndices>,
+{
+    fn handle(&mut self, effs: Effs) -> impl Future<Output = ControlFlow<R, Injs>> {
+        // the handler is called before the future is first polled, like the async handlers are
+        let dispatched = match effs.uninject() {
+            Ok(eff) => Ok((self.head)(eff)),
+            Err(effs) => Err(self.tail.handle(effs)),
+        };
+        async move {
+            match dispatched {
+                Ok(flow) => inject(flow),
+                Err(tail) => tail.await,
+            }
+        }
+    }
+}
+
+// an async handler
+impl<E, F, Fut, Tail, Effs, Injs, R, EffIndex, InjIndex, TailIndices>
+    AsyncHandlerList<Effs, Injs, R, HCons<PhantomData<(E, EffIndex, InjIndex, Fut)>, TailIndices>>
+    for HCons<F, Tail>
+where
+    E: Effect,
+    F: FnMut(E) -> Fut,
+    Fut: Future<Output = ControlFlow<R, E::Injection>>,
+    Effs: CoprodUninjector<E, EffIndex>,
+    Injs: CoprodInjector<Tagged<E::Injection, E>, InjIndex>,
+    Tail: AsyncHandlerList<Effs::Remainder, Injs, R, TailIndices>,
+{
+    fn handle(&mut self, effs: Effs) -> impl Future<Output = ControlFlow<R, Injs>> {
+        let dispatched = match effs.uninject() {
+            Ok(eff) => Ok((self.head)(eff)),
+            Err(effs) => Err(self.tail.handle(effs)),
+        };
+        async move {
+            match dispatched {
+                Ok(head) => inject(head.await),
+                Err(tail) => tail.await,
+            }
+        }
+    }
+}
+
+fn inject<R, E, Injs, InjIndex>(flow: ControlFlow<R, E::Injection>) -> ControlFlow<R, Injs>
+where
+    E: Effect,
+    Injs: CoprodInjector<Tagged<E::Injection, E>, InjIndex>,
+{
+    match flow {
+        ControlFlow::Continue(inj) => ControlFlow::Continue(Injs::inject(Tagged::new(inj))),
+        ControlFlow::Break(ret) => ControlFlow::Break(ret),
+    }
+}
@@ -1,7 +1,7 @@
 use core::marker::PhantomData;
 
 use frunk::{
-    coproduct::{CNil, CoprodInjector},
+    coproduct::{CNil, CoprodInjector, CoprodUninjector},
     indices::{Here, There},
     Coproduct,
 };
@@ -29,7 +29,9 @@ impl<T, Tag> Tagged<T, Tag> {
 pub trait InjectionList {
     type Inj;
     type BeginIndex;
-    type List: CoprodInjector<Self::Inj, Here> + CoprodInjector<Begin, Self::BeginIndex>;
+    type List: CoprodInjector<Self::Inj, Here>
+        + CoprodInjector<Begin, Self::BeginIndex>
+        + CoprodUninjector<Begin, Self::BeginIndex>;
 }
 
 impl InjectionList for CNil {
@@ -1,34 +1,61 @@
-#![feature(generators)]
-#![feature(generator_trait)]
-#![feature(pin_macro)]
+#![cfg_attr(feature = "nightly", feature(generators))]
+#![cfg_attr(feature = "nightly", feature(generator_trait))]
+#![cfg_attr(feature = "nightly", feature(pin_macro))]
+#![cfg_attr(feature = "nightly", feature(generic_associated_types))]
+#![cfg_attr(feature = "nightly", feature(return_position_impl_trait_in_trait))]
 #![no_std]
 
+// lets the crate's own macros be used inside it
+extern crate self as effing_mad;
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
 pub use frunk;
 
+#[macro_use]
+pub mod generator;
+
 pub mod functor_eff;
+pub mod handler_list;
 pub mod injection;
 pub mod macro_impl;
+pub mod std_effects;
+#[cfg(feature = "stream")]
+pub mod stream;
 
+#[cfg(any(not(feature = "nightly"), not(feature = "std")))]
+use core::task::{RawWaker, RawWakerVTable};
 use core::{
+    cell::Cell,
     future::Future,
-    ops::{ControlFlow, Generator, GeneratorState},
-    pin::Pin,
+    marker::PhantomData,
+    mem::ManuallyDrop,
+    ops::ControlFlow,
+    pin::{pin, Pin},
+    ptr,
+    task::{Context, Poll, Waker},
 };
 use frunk::{
     coproduct::{CNil, CoprodInjector, CoprodUninjector, CoproductEmbedder, CoproductSubsetter},
     Coprod, Coproduct,
 };
 
-pub use effing_macros::{effectful, effects, handler};
+pub use effing_macros::{effect, effectful, effectful_expr, effects, handler};
+use generator::{Generator, GeneratorState};
+use handler_list::{AsyncHandlerList, HandlerList};
 use injection::{Begin, InjectionList, Tagged};
+use macro_impl::Prepend;
 
 pub enum Never {}
 
-pub fn run<F, R>(mut f: F) -> R
+pub fn run<F, R>(f: F) -> R
 where
     F: Generator<Coproduct<Begin, CNil>, Yield = CNil, Return = R>,
 {
-    let pinned = core::pin::pin!(f);
+    let pinned = pin!(f);
     match pinned.resume(Coproduct::Inl(Begin)) {
         GeneratorState::Yielded(_) => unreachable!(),
         GeneratorState::Complete(ret) => ret,
@@ -39,6 +66,8 @@ pub trait Effect {
     type Injection;
 }
 
+/// An operation from a group of effects defined with `effects!`, which can be yielded as the whole
+/// group. Operations are also effects in their own right, with the same injection.
 pub trait IntoEffect {
     type Effect: Effect;
     type Injection;
@@ -48,76 +77,763 @@ pub trait IntoEffect {
     fn uninject(injs: <Self::Effect as Effect>::Injection) -> Option<Self::Injection>;
 }
 
-impl<E: Effect> IntoEffect for E {
-    type Effect = Self;
-    type Injection = <Self as Effect>::Injection;
+/// An effect that borrows from the effectful function yielding it, like `Log<'a>(&'a str)`. `At<'b>`
+/// is the same effect borrowing for `'b` instead, which is usually written as
+/// `impl<'a> Lend for Log<'a> { type At<'b> = Log<'b>; }`.
+///
+/// Effectful functions list such an effect as `Lent<Log<'static>>`, and can then yield `Log`s that
+/// borrow their own locals. Handlers are given a [`Lent`], which only lets them look at the effect
+/// until they return.
+pub trait Lend: Effect {
+    type At<'b>: Lend + Effect<Injection = Self::Injection>;
+}
+
+/// An effect borrowing from the effectful function that yielded it. The borrow only lasts until the
+/// function carries on, so the effect can only be looked at through [`Lent::with`].
+///
+/// The function checks that the effect has been dropped before it carries on, and aborts the
+/// process if it hasn't. It does the same if it is dropped while the effect is still around.
+pub struct Lent<E: Lend> {
+    // the lifetime in here is a lie, which `with` replaces with one that can't escape
+    effect: E::At<'static>,
+    loan: Loan,
+}
+
+/// Tells the function that lent an effect that it has been given back.
+struct Loan(*const Cell<bool>);
+
+impl Drop for Loan {
+    fn drop(&mut self) {
+        // safety: the function can't carry on or be dropped while the flag is set, so it is still
+        // there. it is also pinned, so it hasn't moved
+        unsafe { (*self.0).set(false) }
+    }
+}
+
+impl<E: Lend> Effect for Lent<E> {
+    type Injection = E::Injection;
+}
+
+impl<E: Lend> Lent<E> {
+    /// Lend out an effect borrowing for `'a`, which is erased until it is handled. `lent` must be
+    /// pinned in the lending function's frame, and must stop the function carrying on or being
+    /// dropped while it is set.
+    pub(crate) unsafe fn new<'a>(effect: E::At<'a>, lent: &Cell<bool>) -> Self {
+        lent.set(true);
+        Lent {
+            effect: relifetime(effect),
+            loan: Loan(lent),
+        }
+    }
+
+    /// Look at the effect. It borrows for `'b`, which ends before `f` returns.
+    pub fn with<T>(self, f: impl for<'b> FnOnce(E::At<'b>) -> T) -> T {
+        let Lent { effect, loan } = self;
+        // safety: the function hasn't carried on since it lent the effect, since `loan` is still
+        // around, so everything the effect borrows is too. `loan` outlives the call to `f`
+        let ret = f(unsafe { relifetime(effect) });
+        drop(loan);
+        ret
+    }
+}
+
+/// Change the lifetimes in a value's type. The two types must be the same apart from lifetimes,
+/// like the `At<'a>` of a [`Lend`] for different `'a`.
+unsafe fn relifetime<A, B>(a: A) -> B {
+    debug_assert_eq!(core::mem::size_of::<A>(), core::mem::size_of::<B>());
+    let a = ManuallyDrop::new(a);
+    ptr::read(&*a as *const A as *const B)
+}
+
+/// An effect whose injection borrows from its handler, like `&'h mut S` or `&'h [u8]`. `At<'h>` is
+/// the injection borrowing for `'h`, and the effect's actual injection is a [`Borrowed`] of it, so
+/// these effects are written as `impl Effect for Buffer { type Injection = Borrowed<Self>; }` along
+/// with `impl LendInjection for Buffer { type At<'h> = &'h [u8]; }`.
+///
+/// These effects are handled by a [`LendingHandler`] with [`handle_lending`].
+pub trait LendInjection: Effect + Sized {
+    type At<'h>;
+
+    /// Make a [`LendingHandler`] from `state` and a closure that handles the effect by lending out
+    /// part of the state, like
+    /// `Buffer::lend_from(vec![0; 1024], |buf, Buffer| ControlFlow::Continue(&buf[..]))`.
+    ///
+    /// Closures can't return borrows of their own captures, which is why the state is separate.
+    /// This is called on the effect rather than being a free function so that the effect is known
+    /// before the closure is, which the compiler needs to see that the closure lends for any `'h`.
+    fn lend_from<S, R, F>(state: S, handler: F) -> LendFrom<S, F>
+    where
+        F: for<'h> FnMut(&'h mut S, Self) -> ControlFlow<R, Self::At<'h>>,
+    {
+        LendFrom(state, handler)
+    }
+}
+
+/// An injection borrowing from the handler that gave it. The borrow only lasts until the effectful
+/// function yields again, so the injection can only be looked at through [`Borrowed::with`].
+///
+/// The handler checks that the injection has been dropped before it is used again, and aborts the
+/// process if it hasn't. It does the same if it is dropped while the injection is still around.
+pub struct Borrowed<E: LendInjection> {
+    // the lifetime in here is a lie, which `with` replaces with one that can't escape
+    injection: E::At<'static>,
+    loan: Loan,
+}
+
+impl<E: LendInjection> Borrowed<E> {
+    /// Lend out an injection borrowing for `'h`, which is erased until it is looked at. `lent` must
+    /// be pinned in the frame of the handler, and must stop the handler being used or dropped while
+    /// it is set.
+    unsafe fn new<'h>(injection: E::At<'h>, lent: &Cell<bool>) -> Self {
+        lent.set(true);
+        Borrowed {
+            injection: relifetime(injection),
+            loan: Loan(lent),
+        }
+    }
+
+    /// Look at the injection. It borrows for `'h`, which ends before `f` returns.
+    pub fn with<T>(self, f: impl for<'h> FnOnce(E::At<'h>) -> T) -> T {
+        let Borrowed { injection, loan } = self;
+        // safety: the handler hasn't been used since it lent the injection, since `loan` is still
+        // around, so everything the injection borrows is too. `loan` outlives the call to `f`
+        let ret = f(unsafe { relifetime(injection) });
+        drop(loan);
+        ret
+    }
+}
+
+/// Lives next to something that lends out borrows of itself with a [`Loan`], and aborts if it is
+/// dropped while one is still around.
+#[derive(Default)]
+struct LoanGuard(Cell<bool>);
+
+impl LoanGuard {
+    /// Abort if a loan is still around, because `what` is about to be used again.
+    fn check(&self, what: &str) {
+        if self.0.get() {
+            abort(what);
+        }
+    }
+}
+
+impl Drop for LoanGuard {
+    fn drop(&mut self) {
+        self.check("a `Borrowed` injection outlived its handler");
+    }
+}
+
+/// Panicking isn't enough when something still borrows from memory that is about to be used or
+/// freed, since the panic could be caught. Panicking again while unwinding aborts instead.
+#[cold]
+pub(crate) fn abort(message: &str) -> ! {
+    struct Abort<'a>(&'a str);
+
+    impl Drop for Abort<'_> {
+        fn drop(&mut self) {
+            panic!("{}", self.0);
+        }
+    }
+
+    let _abort = Abort(message);
+    panic!("{message}");
+}
+
+/// A group of effects defined with `effects!`. Each operation in the group is an effect of its
+/// own, named after the operation in the group's module, like `state::Put<S>` for `State::put`
+/// (unless that is the name of the group itself).
+/// A computation with the group as one effect can be turned into one with an effect per
+/// operation with [`split_group`]. In the other direction, a computation with the operations as
+/// separate effects can be handled with a handler for the group using [`handle_group`].
+pub trait EffectGroup: Effect + Sized {
+    /// The operations in the group, as a coproduct in the order they were defined.
+    type Members: InjectionList + GroupMembers<Self>;
+
+    fn into_members(self) -> Self::Members;
+    fn into_member_injs(injs: Self::Injection) -> <Self::Members as InjectionList>::List;
+}
+
+/// A coproduct of the operations in group `G`, and their injections.
+pub trait GroupMembers<G: Effect>: InjectionList {
+    fn into_group(self) -> G;
+    /// Gives `None` for `Begin`, which isn't any operation's injection.
+    fn into_group_injs(injs: Self::List) -> Option<G::Injection>;
+}
+
+impl<G: Effect> GroupMembers<G> for CNil {
+    fn into_group(self) -> G {
+        match self {}
+    }
+    fn into_group_injs(_injs: Coproduct<Begin, CNil>) -> Option<G::Injection> {
+        None
+    }
+}
+
+impl<G, O, Tail> GroupMembers<G> for Coproduct<O, Tail>
+where
+    G: Effect,
+    O: Effect + IntoEffect<Effect = G, Injection = <O as Effect>::Injection>,
+    Tail: GroupMembers<G>,
+{
+    fn into_group(self) -> G {
+        match self {
+            Coproduct::Inl(op) => op.into_effect(),
+            Coproduct::Inr(tail) => tail.into_group(),
+        }
+    }
+    fn into_group_injs(injs: Self::List) -> Option<G::Injection> {
+        match injs {
+            Coproduct::Inl(inj) => Some(O::inject(inj.untag())),
+            Coproduct::Inr(tail) => Tail::into_group_injs(tail),
+        }
+    }
+}
+
+/// A handler for effect `E`, used to handle a computation returning `R`.
+///
+/// Closures of the form `FnMut(E) -> ControlFlow<R, E::Injection>` are handlers, as are pairs of
+/// such a closure and a return clause `FnMut(R) -> Output`. Implementing this trait directly is
+/// useful for handlers that need to be named or stored, or that need to clean up after
+/// themselves. The `handler!` macro can also generate a struct implementing it.
+pub trait Handler<E: Effect, R> {
+    /// The type returned by the handled computation.
+    type Output;
+
+    /// Handle an effect, either resuming the computation with an injection or breaking out of it
+    /// with a value.
+    fn handle(&mut self, eff: E) -> ControlFlow<Self::Output, E::Injection>;
+
+    /// Called with the value the computation returns, if it completes without the handler
+    /// breaking out of it. Handlers that have nothing to add can just return `ret` if `Output` is
+    /// `R`.
+    fn on_return(&mut self, ret: R) -> Self::Output;
+
+    /// Called once the handler is finished with, whether that's because the computation returned,
+    /// because the handler broke out of it or because it was dropped part way through.
+    fn on_drop(&mut self) {}
+}
+
+impl<E, R, F> Handler<E, R> for F
+where
+    E: Effect,
+    F: FnMut(E) -> ControlFlow<R, E::Injection>,
+{
+    type Output = R;
+
+    fn handle(&mut self, eff: E) -> ControlFlow<R, E::Injection> {
+        self(eff)
+    }
+    fn on_return(&mut self, ret: R) -> R {
+        ret
+    }
+}
+
+impl<E, R, S, F, Ret> Handler<E, R> for (F, Ret)
+where
+    E: Effect,
+    F: FnMut(E) -> ControlFlow<S, E::Injection>,
+    Ret: FnMut(R) -> S,
+{
+    type Output = S;
+
+    fn handle(&mut self, eff: E) -> ControlFlow<S, E::Injection> {
+        (self.0)(eff)
+    }
+    fn on_return(&mut self, ret: R) -> S {
+        (self.1)(ret)
+    }
+}
+
+/// The async counterpart of [`Handler`], used by [`run_async`] and [`run_blocking`].
+///
+/// Closures of the form `FnMut(E) -> impl Future<Output = ControlFlow<R, E::Injection>>` are async
+/// handlers, as are pairs of such a closure and a return clause `FnMut(R) -> Output`.
+pub trait AsyncHandler<E: Effect, R> {
+    /// The type returned by the handled computation.
+    type Output;
 
-    fn into_effect(self) -> Self {
-        self
+    /// Handle an effect, either resuming the computation with an injection or breaking out of it
+    /// with a value, once the returned future is ready.
+    fn handle(&mut self, eff: E) -> impl Future<Output = ControlFlow<Self::Output, E::Injection>>;
+
+    /// Called with the value the computation returns, if it completes without the handler
+    /// breaking out of it.
+    fn on_return(&mut self, ret: R) -> Self::Output;
+
+    /// Called once the handler is finished with, like [`Handler::on_drop`].
+    fn on_drop(&mut self) {}
+}
+
+impl<E, R, F, Fut> AsyncHandler<E, R> for F
+where
+    E: Effect,
+    F: FnMut(E) -> Fut,
+    Fut: Future<Output = ControlFlow<R, E::Injection>>,
+{
+    type Output = R;
+
+    fn handle(&mut self, eff: E) -> impl Future<Output = ControlFlow<R, E::Injection>> {
+        self(eff)
+    }
+    fn on_return(&mut self, ret: R) -> R {
+        ret
     }
-    fn inject(inj: Self::Injection) -> Self::Injection {
-        inj
+}
+
+impl<E, R, S, F, Fut, Ret> AsyncHandler<E, R> for (F, Ret)
+where
+    E: Effect,
+    F: FnMut(E) -> Fut,
+    Fut: Future<Output = ControlFlow<S, E::Injection>>,
+    Ret: FnMut(R) -> S,
+{
+    type Output = S;
+
+    fn handle(&mut self, eff: E) -> impl Future<Output = ControlFlow<S, E::Injection>> {
+        (self.0)(eff)
     }
-    fn uninject(injs: Self::Injection) -> Option<Self::Injection> {
-        Some(injs)
+    fn on_return(&mut self, ret: R) -> S {
+        (self.1)(ret)
+    }
+}
+
+/// A handler made from an implementation of a handler trait generated by `#[effect]`, such as
+/// `ConsoleHandler` for `#[effect] trait Console`. These handlers always resume the computation.
+///
+/// The generated code can't implement [`Handler`] for implementations of the handler trait
+/// directly, because the orphan rules don't let it implement a trait from this crate for any type
+/// `H`. It can implement it for `Handled<H>` instead.
+pub struct Handled<H>(pub H);
+
+/// A handler for effect `E` whose injections borrow from the handler itself, used to handle a
+/// computation returning `R` with [`handle_lending`]. [`LendInjection::lend_from`] makes one of
+/// these from a closure.
+pub trait LendingHandler<E: LendInjection, R> {
+    /// The type returned by the handled computation.
+    type Output;
+
+    /// Handle an effect, either resuming the computation with an injection borrowing from the
+    /// handler or breaking out of it with a value.
+    fn handle(&mut self, eff: E) -> ControlFlow<Self::Output, E::At<'_>>;
+
+    /// Called with the value the computation returns, if it completes without the handler
+    /// breaking out of it.
+    fn on_return(&mut self, ret: R) -> Self::Output;
+}
+
+/// A [`LendingHandler`] made with [`LendInjection::lend_from`].
+pub struct LendFrom<S, F>(S, F);
+
+impl<S, E, R, F> LendingHandler<E, R> for LendFrom<S, F>
+where
+    E: LendInjection,
+    F: for<'h> FnMut(&'h mut S, E) -> ControlFlow<R, E::At<'h>>,
+{
+    type Output = R;
+
+    fn handle(&mut self, eff: E) -> ControlFlow<R, E::At<'_>> {
+        (self.1)(&mut self.0, eff)
+    }
+    fn on_return(&mut self, ret: R) -> R {
+        ret
+    }
+}
+
+/// Calls `Handler::on_drop` when dropped, so that handlers get cleaned up however handling ends.
+/// It is made along with the handled computation rather than inside it, so that it is dropped even
+/// if the computation never starts.
+struct DropGuard<H: Handler<E, R>, E: Effect, R>(H, PhantomData<fn(E, R)>);
+
+impl<H: Handler<E, R>, E: Effect, R> Drop for DropGuard<H, E, R> {
+    fn drop(&mut self) {
+        self.0.on_drop();
+    }
+}
+
+/// [`DropGuard`] for an [`AsyncHandler`].
+struct AsyncDropGuard<H: AsyncHandler<E, R>, E: Effect, R>(H, PhantomData<fn(E, R)>);
+
+impl<H: AsyncHandler<E, R>, E: Effect, R> Drop for AsyncDropGuard<H, E, R> {
+    fn drop(&mut self) {
+        self.0.on_drop();
     }
 }
 
 pub fn map<E, I, T, U>(
-    mut g: impl Generator<I, Yield = E, Return = T>,
+    g: impl Generator<I, Yield = E, Return = T>,
     f: impl FnOnce(T) -> U,
 ) -> impl Generator<I, Yield = E, Return = U> {
-    move |mut injs: I| {
+    generator!(static move |mut injs: I| {
+        let mut g = pin!(g);
         loop {
-            // safety: see handle()
-            let pinned = unsafe { Pin::new_unchecked(&mut g) };
-            match pinned.resume(injs) {
-                GeneratorState::Yielded(effs) => injs = yield effs,
+            match g.as_mut().resume(injs) {
+                GeneratorState::Yielded(effs) => injs = yield_!(effs),
                 GeneratorState::Complete(ret) => return f(ret),
             }
         }
-    }
+    })
 }
 
-pub fn handle<G, R, E, PreEs, PostEs, EffIndex, PreIs, PostIs, BeginIndex, InjIndex, EmbedIndices>(
-    mut g: G,
-    mut handler: impl FnMut(E) -> ControlFlow<R, E::Injection>,
-) -> impl Generator<PostIs, Yield = PostEs, Return = R>
+pub fn handle<
+    G,
+    R,
+    E,
+    H,
+    PreEs,
+    PostEs,
+    EffIndex,
+    PreIs,
+    PostIs,
+    BeginIndex,
+    InjIndex,
+    EmbedIndices,
+>(
+    g: G,
+    handler: H,
+) -> impl Generator<PostIs, Yield = PostEs, Return = H::Output>
 where
     E: Effect,
+    H: Handler<E, R>,
     PreEs: InjectionList<List = PreIs> + CoprodUninjector<E, EffIndex, Remainder = PostEs>,
     PostEs: InjectionList<List = PostIs>,
     PreIs: CoprodInjector<Begin, BeginIndex> + CoprodInjector<Tagged<E::Injection, E>, InjIndex>,
     PostIs: CoproductEmbedder<PreIs, EmbedIndices>,
     G: Generator<PreIs, Yield = PreEs, Return = R>,
 {
-    move |_begin: PostIs| {
+    let mut handler = DropGuard(handler, PhantomData);
+    generator!(static move |_begin: PostIs| {
+        let mut g = pin!(g);
         let mut injection = PreIs::inject(Begin);
         loop {
-            // safety: im 90% sure that since we are inside Generator::resume, which takes
-            // Pin<&mut self>, all locals in this function are effectively pinned and this call is
-            // simply projecting that
-            let pinned = unsafe { Pin::new_unchecked(&mut g) };
-            match pinned.resume(injection) {
+            match g.as_mut().resume(injection) {
                 GeneratorState::Yielded(effs) => match effs.uninject() {
                     // the effect we are handling
-                    Ok(eff) => match handler(eff) {
+                    Ok(eff) => match handler.0.handle(eff) {
                         ControlFlow::Continue(inj) => injection = PreIs::inject(Tagged::new(inj)),
                         ControlFlow::Break(ret) => return ret,
                     },
                     // any other effect
                     Err(effs) => {
                         let effs: PostEs = effs;
-                        let inj = yield effs;
+                        let inj = yield_!(effs);
+                        injection = inj.embed();
+                    }
+                },
+                GeneratorState::Complete(ret) => return handler.0.on_return(ret),
+            }
+        }
+    })
+}
+
+/// Like [`handle`], but the return value of `g` is passed through `on_return` when it completes
+/// normally. This is the equivalent of a `return` clause in other languages' effect handlers, and
+/// allows the handled computation to return a different type than `g` - for example, a handler
+/// that can break out of `g` early can give back a `Result` that says whether it did.
+pub fn handle_with_return<
+    G,
+    R,
+    S,
+    E,
+    PreEs,
+    PostEs,
+    EffIndex,
+    PreIs,
+    PostIs,
+    BeginIndex,
+    InjIndex,
+    EmbedIndices,
+>(
+    g: G,
+    on_effect: impl FnMut(E) -> ControlFlow<S, E::Injection>,
+    on_return: impl FnOnce(R) -> S,
+) -> impl Generator<PostIs, Yield = PostEs, Return = S>
+where
+    E: Effect,
+    PreEs: InjectionList<List = PreIs> + CoprodUninjector<E, EffIndex, Remainder = PostEs>,
+    PostEs: InjectionList<List = PostIs>,
+    PreIs: CoprodInjector<Begin, BeginIndex> + CoprodInjector<Tagged<E::Injection, E>, InjIndex>,
+    PostIs: CoproductEmbedder<PreIs, EmbedIndices>,
+    G: Generator<PreIs, Yield = PreEs, Return = R>,
+{
+    // the computation can only return once, but handlers are called through &mut
+    let mut on_return = Some(on_return);
+    handle(g, (on_effect, move |ret| (on_return.take().unwrap())(ret)))
+}
+
+/// Like [`handle`], but for an effect whose injections borrow from the handler, such as a
+/// `&'h mut S` to some state that would otherwise have to be cloned on every access. The effectful
+/// function gets a [`Borrowed`], which it has to drop before it yields again or returns.
+pub fn handle_lending<
+    G,
+    R,
+    E,
+    H,
+    PreEs,
+    PostEs,
+    EffIndex,
+    PreIs,
+    PostIs,
+    BeginIndex,
+    InjIndex,
+    EmbedIndices,
+>(
+    g: G,
+    handler: H,
+) -> impl Generator<PostIs, Yield = PostEs, Return = H::Output>
+where
+    E: LendInjection<Injection = Borrowed<E>>,
+    H: LendingHandler<E, R>,
+    PreEs: InjectionList<List = PreIs> + CoprodUninjector<E, EffIndex, Remainder = PostEs>,
+    PostEs: InjectionList<List = PostIs>,
+    PreIs: CoprodInjector<Begin, BeginIndex> + CoprodInjector<Tagged<Borrowed<E>, E>, InjIndex>,
+    PostIs: CoproductEmbedder<PreIs, EmbedIndices>,
+    G: Generator<PreIs, Yield = PreEs, Return = R>,
+{
+    generator!(static move |_begin: PostIs| {
+        // declared before `g` so that they are dropped after it, along with anything it borrowed
+        let mut handler = handler;
+        let lent = LoanGuard::default();
+        let mut g = pin!(g);
+        let mut injection = PreIs::inject(Begin);
+        loop {
+            let state = g.as_mut().resume(injection);
+            // whatever the computation does next, it can pass on what it was lent, so it must have
+            // given it back already
+            lent.check("a `Borrowed` injection was kept after yielding or returning");
+            match state {
+                GeneratorState::Yielded(effs) => match effs.uninject() {
+                    // the effect we are handling
+                    Ok(eff) => match handler.handle(eff) {
+                        ControlFlow::Continue(inj) => {
+                            // safety: `lent` is pinned in the frame of a static generator, and the
+                            // handler isn't used again until it is checked
+                            let inj = unsafe { Borrowed::new(inj, &lent.0) };
+                            injection = PreIs::inject(Tagged::new(inj));
+                        }
+                        ControlFlow::Break(ret) => return ret,
+                    },
+                    // any other effect
+                    Err(effs) => {
+                        let effs: PostEs = effs;
+                        let inj = yield_!(effs);
                         injection = inj.embed();
                     }
                 },
+                GeneratorState::Complete(ret) => return handler.on_return(ret),
+            }
+        }
+    })
+}
+
+/// Like [`handle`], but the handler can give an effect back with `Err` instead of handling it, in
+/// which case it is yielded on to an outer handler. This means `E` is still one of the effects of
+/// the handled computation.
+///
+/// The `handler!` macro builds these handlers for groups of effects when the arms end with `..`,
+/// which forwards every operation that doesn't have an arm.
+pub fn handle_partial<G, R, E, Es, Is, Rem, EffIndex, BeginIndex, InjIndex, EmbedIndices>(
+    g: G,
+    mut handler: impl FnMut(E) -> Result<ControlFlow<R, E::Injection>, E>,
+) -> impl Generator<Is, Yield = Es, Return = R>
+where
+    E: Effect,
+    Es: InjectionList<List = Is>
+        + CoprodInjector<E, EffIndex>
+        + CoprodUninjector<E, EffIndex, Remainder = Rem>,
+    Rem: CoproductEmbedder<Es, EmbedIndices>,
+    Is: CoprodInjector<Begin, BeginIndex> + CoprodInjector<Tagged<E::Injection, E>, InjIndex>,
+    G: Generator<Is, Yield = Es, Return = R>,
+{
+    generator!(static move |_begin: Is| {
+        let mut g = pin!(g);
+        let mut injection = Is::inject(Begin);
+        loop {
+            match g.as_mut().resume(injection) {
+                GeneratorState::Yielded(effs) => match effs.uninject() {
+                    // the effect we are handling
+                    Ok(eff) => match handler(eff) {
+                        Ok(ControlFlow::Continue(inj)) => injection = Is::inject(Tagged::new(inj)),
+                        Ok(ControlFlow::Break(ret)) => return ret,
+                        // one the handler left for someone else
+                        Err(eff) => injection = yield_!(Es::inject(eff)),
+                    },
+                    // any other effect
+                    Err(effs) => injection = yield_!(effs.embed()),
+                },
                 GeneratorState::Complete(ret) => return ret,
             }
         }
+    })
+}
+
+/// Handle several effects at once, using an `HList` of handlers with one handler per effect. The
+/// handlers all have mutable access to `state`, which allows them to share a resource even though
+/// they handle unrelated effects. See [`HandlerList`] for the form each handler must take.
+///
+/// The `handler!` macro can build the list of handlers, or it can be written out with
+/// `frunk::hlist!`. Either way, closures need the type of their state argument spelled out.
+pub fn handle_many<
+    G,
+    R,
+    S,
+    H,
+    PreEs,
+    PostEs,
+    PreIs,
+    PostIs,
+    BeginIndex,
+    EmbedIndices,
+    HandlerIndices,
+>(
+    g: G,
+    mut state: S,
+    mut handlers: H,
+) -> impl Generator<PostIs, Yield = PostEs, Return = R>
+where
+    PreEs: InjectionList<List = PreIs>,
+    PostEs: InjectionList<List = PostIs>,
+    PreIs: CoprodInjector<Begin, BeginIndex>,
+    PostIs: CoproductEmbedder<PreIs, EmbedIndices>,
+    H: HandlerList<S, PreEs, PreIs, R, HandlerIndices, Remainder = PostEs>,
+    G: Generator<PreIs, Yield = PreEs, Return = R>,
+{
+    generator!(static move |_begin: PostIs| {
+        let mut g = pin!(g);
+        let mut injection = PreIs::inject(Begin);
+        loop {
+            match g.as_mut().resume(injection) {
+                GeneratorState::Yielded(effs) => match handlers.handle(&mut state, effs) {
+                    // one of the effects we are handling
+                    Ok(ControlFlow::Continue(inj)) => injection = inj,
+                    Ok(ControlFlow::Break(ret)) => return ret,
+                    // any other effect
+                    Err(effs) => {
+                        let inj = yield_!(effs);
+                        injection = inj.embed();
+                    }
+                },
+                GeneratorState::Complete(ret) => return ret,
+            }
+        }
+    })
+}
+
+/// Handle the last remaining effect of a cloneable generator, allowing the handler to resume the
+/// computation any number of times (including zero) with different injections.
+///
+/// Each time `g` yields, the handler is passed the effect and a [`Continuation`] holding the
+/// suspended computation. Calling [`Continuation::resume`] runs a fresh copy of that computation to
+/// completion, handling further effects with the same handler, and returns its result. This is
+/// what allows handlers for effects like nondeterministic choice to explore every branch.
+///
+/// Since the handler can be re-entered from inside a continuation, it is `Fn` rather than `FnMut`.
+/// Note that `#[effectful]` functions only return `Clone` generators if they ask for it, e.g.
+/// `#[effectful(Choose; Clone)]`, which in turn requires `#![feature(generator_clone)]` and so the
+/// `nightly` feature. Unlike other effectful functions, these can't hold borrows of their locals
+/// across `yield`s, use `.do_` or lend effects, since a generator that borrows from itself can't be
+/// cloned.
+pub fn handle_multishot<G, R, E>(g: G, handler: impl Fn(E, &Continuation<'_, G, E, R>) -> R) -> R
+where
+    E: Effect,
+    G: Generator<Coprod!(Tagged<E::Injection, E>, Begin), Yield = Coprod!(E), Return = R> + Clone,
+{
+    handle_multishot_with_return(g, handler, core::convert::identity)
+}
+
+/// Like [`handle_multishot`], but the value returned by each branch of the computation is passed
+/// through `on_return`. This lets the handler produce a different type than `g` returns, such as a
+/// collection of the results of every branch.
+pub fn handle_multishot_with_return<G, R, S, E>(
+    g: G,
+    on_effect: impl Fn(E, &Continuation<'_, G, E, S>) -> S,
+    on_return: impl Fn(R) -> S,
+) -> S
+where
+    E: Effect,
+    G: Generator<Coprod!(Tagged<E::Injection, E>, Begin), Yield = Coprod!(E), Return = R> + Clone,
+{
+    resume_multishot(g, Coproduct::inject(Begin), &(on_effect, on_return))
+}
+
+fn resume_multishot<G, S, E>(
+    g: G,
+    injection: Coprod!(Tagged<E::Injection, E>, Begin),
+    handler: &dyn MultishotHandler<G, E, S>,
+) -> S
+where
+    E: Effect,
+    G: Generator<Coprod!(Tagged<E::Injection, E>, Begin), Yield = Coprod!(E)> + Clone,
+{
+    let mut pinned = pin!(g);
+    match pinned.as_mut().resume(injection) {
+        GeneratorState::Yielded(eff) => {
+            let continuation = Continuation {
+                suspended: pinned.into_ref(),
+                handler,
+            };
+            handler.on_effect(eff.take().unwrap(), &continuation)
+        }
+        GeneratorState::Complete(ret) => handler.on_return(ret),
+    }
+}
+
+/// The effect and return handlers passed to [`handle_multishot_with_return`], type-erased so that
+/// continuations don't have to name the handlers' types.
+trait MultishotHandler<G, E, S>
+where
+    E: Effect,
+    G: Generator<Coprod!(Tagged<E::Injection, E>, Begin)>,
+{
+    fn on_effect(&self, eff: E, continuation: &Continuation<'_, G, E, S>) -> S;
+    fn on_return(&self, ret: G::Return) -> S;
+}
+
+impl<G, E, S, F, Ret> MultishotHandler<G, E, S> for (F, Ret)
+where
+    E: Effect,
+    G: Generator<Coprod!(Tagged<E::Injection, E>, Begin)>,
+    F: Fn(E, &Continuation<'_, G, E, S>) -> S,
+    Ret: Fn(G::Return) -> S,
+{
+    fn on_effect(&self, eff: E, continuation: &Continuation<'_, G, E, S>) -> S {
+        (self.0)(eff, continuation)
+    }
+    fn on_return(&self, ret: G::Return) -> S {
+        (self.1)(ret)
+    }
+}
+
+/// A suspended computation passed to the handler given to [`handle_multishot`].
+pub struct Continuation<'a, G, E, S>
+where
+    E: Effect,
+    G: Generator<Coprod!(Tagged<E::Injection, E>, Begin)>,
+{
+    suspended: Pin<&'a G>,
+    handler: &'a dyn MultishotHandler<G, E, S>,
+}
+
+impl<'a, G, E, S> Continuation<'a, G, E, S>
+where
+    E: Effect,
+    G: Generator<Coprod!(Tagged<E::Injection, E>, Begin), Yield = Coprod!(E)> + Clone,
+{
+    /// Resume a copy of the suspended computation with the given injection and run it to
+    /// completion. The continuation itself is left untouched, so it can be resumed again.
+    pub fn resume(&self, injection: E::Injection) -> S {
+        // cloning out of the pin is fine: the clone is a new value which has never been pinned
+        let g = G::clone(&self.suspended);
+        resume_multishot(g, Coproduct::inject(Tagged::new(injection)), self.handler)
     }
 }
 
+/// Handle effect `E` by turning it into other effects. Each time `g` yields `E`, `handler` is
+/// called with it and gives back a computation, which is run in place of the effect. Its effects
+/// are passed on along with the rest of `g`'s, and `g` is resumed with what it returns.
+///
+/// Unlike [`handle`], this takes a closure rather than a [`Handler`], since the handler answers
+/// each effect with a computation rather than a [`ControlFlow`]. There is nothing for `on_return`
+/// to do either, as the handler can't break out of `g`, so `g`'s return value is passed through.
 pub fn transform<
     G1,
     R,
@@ -142,7 +858,7 @@ pub fn transform<
     EmbedIndices2,
     EmbedIndices3,
 >(
-    mut g: G1,
+    g: G1,
     mut handler: impl FnMut(E) -> H,
 ) -> impl Generator<PostIs, Yield = PostEs, Return = R>
 where
@@ -163,23 +879,21 @@ where
         > + CoproductSubsetter<HandlerIs, SubsetIndices2>,
     G1: Generator<PreIs, Yield = PreEs, Return = R>,
 {
-    move |_begin: PostIs| {
+    generator!(static move |_begin: PostIs| {
+        let mut g = pin!(g);
         let mut injection = PreIs::inject(Begin);
         loop {
-            // safety: see handle()
-            let pinned = unsafe { Pin::new_unchecked(&mut g) };
-            match pinned.resume(injection) {
+            match g.as_mut().resume(injection) {
                 GeneratorState::Yielded(effs) => match effs.uninject() {
                     // the effect we are handling
                     Ok(eff) => {
-                        let mut handling = handler(eff);
+                        let mut handling = pin!(handler(eff));
                         let mut handler_inj = HandlerIs::inject(Begin);
                         'run_handler: loop {
-                            // safety: same again
-                            let pinned = unsafe { Pin::new_unchecked(&mut handling) };
-                            match pinned.resume(handler_inj) {
+                            match handling.as_mut().resume(handler_inj) {
                                 GeneratorState::Yielded(effs) => {
-                                    handler_inj = PostIs::subset(yield effs.embed()).ok().unwrap()
+                                    handler_inj =
+                                        PostIs::subset(yield_!(effs.embed())).ok().unwrap()
                                 }
                                 GeneratorState::Complete(inj) => {
                                     injection = PreIs::inject(Tagged::new(inj));
@@ -191,13 +905,13 @@ where
                     // any other effect
                     Err(effs) => {
                         injection =
-                            PreHandleIs::embed(PostIs::subset(yield effs.embed()).ok().unwrap())
+                            PreHandleIs::embed(PostIs::subset(yield_!(effs.embed())).ok().unwrap())
                     }
                 },
                 GeneratorState::Complete(ret) => return ret,
             }
         }
-    }
+    })
 }
 
 pub fn transform0<
@@ -294,20 +1008,236 @@ where
     transform(g, handler)
 }
 
-pub async fn run_async<Eff, G, R, H, Fut>(mut g: G, mut handler: H) -> G::Return
+/// Turn the effect group `Grp` of a computation into one effect per operation in the group, so
+/// that the operations can be handled separately. The operations come before the computation's
+/// other effects.
+pub fn split_group<
+    G,
+    R,
+    Grp,
+    PreEs,
+    PostEs,
+    Rem,
+    PreIs,
+    PostIs,
+    Left,
+    GroupIndex,
+    BeginIndex,
+    InjIndex,
+    MembersIndices,
+    RemIndices,
+    SubsetIndices,
+    EmbedIndices,
+>(
+    g: G,
+) -> impl Generator<PostIs, Yield = PostEs, Return = R>
+where
+    Grp: EffectGroup,
+    Grp::Members: Prepend<Rem, Out = PostEs> + CoproductEmbedder<PostEs, MembersIndices>,
+    PreEs: InjectionList<List = PreIs> + CoprodUninjector<Grp, GroupIndex, Remainder = Rem>,
+    Rem: CoproductEmbedder<PostEs, RemIndices>,
+    PostEs: InjectionList<List = PostIs>,
+    PreIs:
+        CoprodInjector<Begin, BeginIndex> + CoprodInjector<Tagged<Grp::Injection, Grp>, InjIndex>,
+    PostIs:
+        CoproductSubsetter<<Grp::Members as InjectionList>::List, SubsetIndices, Remainder = Left>,
+    Left: CoproductEmbedder<PreIs, EmbedIndices>,
+    G: Generator<PreIs, Yield = PreEs, Return = R>,
+{
+    generator!(static move |_begin: PostIs| {
+        let mut g = pin!(g);
+        let mut injection = PreIs::inject(Begin);
+        loop {
+            match g.as_mut().resume(injection) {
+                GeneratorState::Yielded(effs) => {
+                    let effs: PostEs = match effs.uninject() {
+                        Ok(group) => group.into_members().embed(),
+                        Err(effs) => effs.embed(),
+                    };
+                    injection = match yield_!(effs).subset() {
+                        Ok(injs) => match Grp::Members::into_group_injs(injs) {
+                            Some(inj) => PreIs::inject(Tagged::new(inj)),
+                            None => PreIs::inject(Begin),
+                        },
+                        Err(injs) => injs.embed(),
+                    };
+                }
+                GeneratorState::Complete(ret) => return ret,
+            }
+        }
+    })
+}
+
+/// The reverse of [`split_group`]: handle the operations of a computation that belong to effect
+/// group `Grp` with a handler for the whole group. The computation must have every operation in
+/// the group as an effect.
+pub fn handle_group<
+    G,
+    R,
+    Grp,
+    H,
+    PreEs,
+    PostEs,
+    PreIs,
+    PostIs,
+    BeginIndex,
+    SubsetIndices,
+    MembersIndices,
+    EmbedIndices,
+>(
+    g: G,
+    handler: H,
+) -> impl Generator<PostIs, Yield = PostEs, Return = H::Output>
+where
+    Grp: EffectGroup,
+    H: Handler<Grp, R>,
+    <Grp::Members as InjectionList>::List: CoproductEmbedder<PreIs, MembersIndices>,
+    PreEs: InjectionList<List = PreIs>
+        + CoproductSubsetter<Grp::Members, SubsetIndices, Remainder = PostEs>,
+    PostEs: InjectionList<List = PostIs>,
+    PreIs: CoprodInjector<Begin, BeginIndex>,
+    PostIs: CoproductEmbedder<PreIs, EmbedIndices>,
+    G: Generator<PreIs, Yield = PreEs, Return = R>,
+{
+    let mut handler = DropGuard(handler, PhantomData);
+    generator!(static move |_begin: PostIs| {
+        let mut g = pin!(g);
+        let mut injection = PreIs::inject(Begin);
+        loop {
+            match g.as_mut().resume(injection) {
+                GeneratorState::Yielded(effs) => match effs.subset() {
+                    // an operation from the group we are handling
+                    Ok(members) => match handler.0.handle(members.into_group()) {
+                        ControlFlow::Continue(inj) => {
+                            injection = Grp::into_member_injs(inj).embed()
+                        }
+                        ControlFlow::Break(ret) => return ret,
+                    },
+                    // any other effect
+                    Err(effs) => {
+                        let inj = yield_!(effs);
+                        injection = inj.embed();
+                    }
+                },
+                GeneratorState::Complete(ret) => return handler.0.on_return(ret),
+            }
+        }
+    })
+}
+
+/// Run a computation whose only effect is `Eff`, handling it with an async handler. See
+/// [`AsyncHandler`] for the forms the handler can take.
+pub fn run_async<Eff, G, R, H>(g: G, handler: H) -> impl Future<Output = H::Output>
+where
+    Eff: Effect,
+    G: Generator<Coprod!(Tagged<Eff::Injection, Eff>, Begin), Yield = Coprod!(Eff), Return = R>,
+    H: AsyncHandler<Eff, R>,
+{
+    let mut handler = AsyncDropGuard(handler, PhantomData);
+    async move {
+        let mut g = pin!(g);
+        let mut inj = Coproduct::inject(Begin);
+        loop {
+            match g.as_mut().resume(inj) {
+                GeneratorState::Yielded(eff) => match handler.0.handle(eff.take().unwrap()).await {
+                    ControlFlow::Continue(new_inj) => inj = Coproduct::inject(Tagged::new(new_inj)),
+                    ControlFlow::Break(ret) => return ret,
+                },
+                GeneratorState::Complete(ret) => return handler.0.on_return(ret),
+            }
+        }
+    }
+}
+
+/// Like [`run_async`], but blocks until the computation is finished instead of returning a future.
+/// This means the same async handler can be used from both sync and async code.
+///
+/// With the `std` feature, the thread is parked while the handler's future waits, until it is
+/// woken. Without it, the future is polled in a loop until it is ready, so handlers that wait for
+/// long keep the thread busy. Either way, futures that need a runtime of their own (like most that
+/// do I/O) still need that runtime to be running, for example on other threads.
+pub fn run_blocking<Eff, G, R, H>(g: G, handler: H) -> H::Output
 where
     Eff: Effect,
     G: Generator<Coprod!(Tagged<Eff::Injection, Eff>, Begin), Yield = Coprod!(Eff), Return = R>,
-    H: FnMut(Eff) -> Fut,
-    Fut: Future<Output = ControlFlow<R, Eff::Injection>>,
+    H: AsyncHandler<Eff, R>,
+{
+    block_on(run_async(g, handler))
+}
+
+// a waker that does nothing, except carry `data` around. The generators faked on stable use it to
+// find their airlock, and `block_on` uses it when it can't park the thread
+#[cfg(any(not(feature = "nightly"), not(feature = "std")))]
+pub(crate) static NOOP_WAKER: RawWakerVTable = RawWakerVTable::new(
+    |data| RawWaker::new(data, &NOOP_WAKER),
+    |_| (),
+    |_| (),
+    |_| (),
+);
+
+#[cfg(any(not(feature = "nightly"), not(feature = "std")))]
+pub(crate) fn noop_waker(data: *const ()) -> Waker {
+    // safety: the vtable's functions do nothing, so they can't break any contract
+    unsafe { Waker::from_raw(RawWaker::new(data, &NOOP_WAKER)) }
+}
+
+/// Wakes a thread parked in [`block_on`].
+#[cfg(feature = "std")]
+struct Unpark(std::thread::Thread);
+
+#[cfg(feature = "std")]
+impl alloc::task::Wake for Unpark {
+    fn wake(self: alloc::sync::Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &alloc::sync::Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    #[cfg(feature = "std")]
+    let waker = Waker::from(alloc::sync::Arc::new(Unpark(std::thread::current())));
+    // nothing is ever woken, since the future is polled again straight away regardless
+    #[cfg(not(feature = "std"))]
+    let waker = noop_waker(ptr::null());
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(ret) = future.as_mut().poll(&mut cx) {
+            return ret;
+        }
+        // parking can wake up for no reason, but then the future is just polled again
+        #[cfg(feature = "std")]
+        std::thread::park();
+        #[cfg(not(feature = "std"))]
+        core::hint::spin_loop();
+    }
+}
+
+/// Run a computation to completion, handling all of its effects with an `HList` of handlers, any
+/// of which may be async. See [`AsyncHandlerList`] for the forms the handlers can take.
+///
+/// Unlike [`handle_many`], the handlers don't share any state, since an async handler's future
+/// could not hold on to it. Handlers that need to share something can each capture a reference to
+/// it instead.
+pub async fn run_async_many<G, R, H, Effs, Injs, BeginIndex, HandlerIndices>(
+    g: G,
+    mut handlers: H,
+) -> R
+where
+    Effs: InjectionList<List = Injs>,
+    Injs: CoprodInjector<Begin, BeginIndex>,
+    H: AsyncHandlerList<Effs, Injs, R, HandlerIndices>,
+    G: Generator<Injs, Yield = Effs, Return = R>,
 {
-    let mut inj = Coproduct::inject(Begin);
+    let mut g = pin!(g);
+    let mut injection = Injs::inject(Begin);
     loop {
-        // safety: see handle() - remember that futures are pinned in the same way as generators
-        let pinned = unsafe { Pin::new_unchecked(&mut g) };
-        match pinned.resume(inj) {
-            GeneratorState::Yielded(eff) => match handler(eff.take().unwrap()).await {
-                ControlFlow::Continue(new_inj) => inj = Coproduct::inject(Tagged::new(new_inj)),
+        match g.as_mut().resume(injection) {
+            GeneratorState::Yielded(effs) => match handlers.handle(effs).await {
+                ControlFlow::Continue(inj) => injection = inj,
                 ControlFlow::Break(ret) => return ret,
             },
             GeneratorState::Complete(ret) => return ret,
@@ -1,11 +1,18 @@
-use core::marker::PhantomData;
+use core::{cell::Cell, marker::PhantomData};
 
 use frunk::{
-    coproduct::{CNil, CoprodUninjector},
-    Coproduct,
+    coproduct::{CNil, CoprodInjector, CoprodUninjector},
+    indices::{Here, There},
+    Coproduct, HCons, HNil,
 };
 
-use crate::{injection::Tagged, Effect, IntoEffect};
+use crate::{
+    abort,
+    generator::Generator,
+    injection::{InjectionList, Tagged},
+    std_effects::Throw,
+    Effect, IntoEffect, Lend, Lent, Never,
+};
 
 /// Construct a PhantomData with the type of an expression
 #[must_use]
@@ -13,20 +20,241 @@ pub fn mark<T>(_: &T) -> PhantomData<T> {
     PhantomData
 }
 
-pub fn get_inj<E, Injs, Index>(injs: Injs, _marker: PhantomData<E>) -> Option<E::Injection>
+/// An index for [`YieldEffect`] and [`ResumeEffect`], for yielding an effect as itself.
+pub struct Itself<Index>(PhantomData<Index>);
+
+/// An index for [`YieldEffect`] and [`ResumeEffect`], for yielding an operation from an `effects!`
+/// group as the whole group.
+pub struct AsGroup<Index>(PhantomData<Index>);
+
+/// An index for [`YieldEffect`] and [`ResumeEffect`], for lending an effect that borrows from the
+/// function yielding it as a `Lent<E>`.
+pub struct Lending<E, Index>(PhantomData<(E, Index)>);
+
+/// A value that can be yielded by an effectful function with effects `Effs`. Operations from an
+/// `effects!` group are effects in their own right, so they can be yielded either by themselves or
+/// as their group, depending on which of the two the function has. Effects that borrow from the
+/// function are yielded as a [`Lent`], which needs the function's [`LendGuard`].
+pub trait YieldEffect<Effs, Index>: Effect {
+    fn into_effs(self, guard: &LendGuard) -> Effs;
+}
+
+/// A [`YieldEffect`] that doesn't lend anything out, for movable generators that have nowhere to
+/// keep a [`LendGuard`].
+pub trait YieldOwned<Effs, Index>: YieldEffect<Effs, Index> {
+    fn into_owned_effs(self) -> Effs;
+}
+
+impl<E, Effs, Index> YieldEffect<Effs, Itself<Index>> for E
+where
+    E: Effect,
+    Effs: CoprodInjector<E, Index>,
+{
+    fn into_effs(self, _guard: &LendGuard) -> Effs {
+        self.into_owned_effs()
+    }
+}
+
+impl<E, Effs, Index> YieldOwned<Effs, Itself<Index>> for E
+where
+    E: Effect,
+    Effs: CoprodInjector<E, Index>,
+{
+    fn into_owned_effs(self) -> Effs {
+        Effs::inject(self)
+    }
+}
+
+impl<O, Effs, Index> YieldEffect<Effs, AsGroup<Index>> for O
+where
+    O: Effect + IntoEffect,
+    Effs: CoprodInjector<O::Effect, Index>,
+{
+    fn into_effs(self, _guard: &LendGuard) -> Effs {
+        self.into_owned_effs()
+    }
+}
+
+impl<O, Effs, Index> YieldOwned<Effs, AsGroup<Index>> for O
+where
+    O: Effect + IntoEffect,
+    Effs: CoprodInjector<O::Effect, Index>,
+{
+    fn into_owned_effs(self) -> Effs {
+        Effs::inject(self.into_effect())
+    }
+}
+
+impl<'a, B, E, Effs, Index> YieldEffect<Effs, Lending<E, Index>> for B
+where
+    B: Lend,
+    E: Lend<At<'a> = B>,
+    Effs: CoprodInjector<Lent<E>, Index>,
+{
+    fn into_effs(self, guard: &LendGuard) -> Effs {
+        // safety: only static generators have a guard, which is pinned in their frame
+        Effs::inject(unsafe { Lent::<E>::new(self, &guard.0) })
+    }
+}
+
+/// Lives in the frame of an effectful function across a `yield`, and aborts if the function carries
+/// on or is dropped while the effect it yielded is still lent out. Only static generators have one,
+/// since the [`Lent`] keeps a pointer to it.
+#[derive(Default)]
+pub struct LendGuard(Cell<bool>);
+
+impl Drop for LendGuard {
+    fn drop(&mut self) {
+        if self.0.get() {
+            abort("a `Lent` effect outlived the `yield` it came from");
+        }
+    }
+}
+
+/// Lets the generator returned by an effectful function borrow for `'a`. Unlike `+ 'a`, this
+/// doesn't need the generator to outlive `'a`, so it can borrow for several lifetimes at once.
+pub trait Captures<'a> {}
+
+impl<'a, T: ?Sized> Captures<'a> for T {}
+
+/// Gets the injection for a value yielded with [`YieldEffect`] back out of the injections an
+/// effectful function is resumed with.
+pub trait ResumeEffect<Injs, Index>: Effect {
+    fn from_injs(injs: Injs) -> Option<Self::Injection>;
+}
+
+impl<E, Injs, Index> ResumeEffect<Injs, Itself<Index>> for E
 where
     E: Effect,
     Injs: CoprodUninjector<Tagged<E::Injection, E>, Index>,
 {
-    injs.uninject().ok().map(Tagged::untag)
+    fn from_injs(injs: Injs) -> Option<E::Injection> {
+        injs.uninject().ok().map(Tagged::untag)
+    }
+}
+
+impl<O, Injs, Index> ResumeEffect<Injs, AsGroup<Index>> for O
+where
+    O: Effect + IntoEffect<Injection = <O as Effect>::Injection>,
+    Injs: CoprodUninjector<Tagged<<O::Effect as Effect>::Injection, O::Effect>, Index>,
+{
+    fn from_injs(injs: Injs) -> Option<<O as Effect>::Injection> {
+        O::uninject(injs.uninject().ok()?.untag())
+    }
+}
+
+impl<'a, B, E, Injs, Index> ResumeEffect<Injs, Lending<E, Index>> for B
+where
+    B: Lend<Injection = E::Injection>,
+    E: Lend<At<'a> = B>,
+    Injs: CoprodUninjector<Tagged<E::Injection, Lent<E>>, Index>,
+{
+    fn from_injs(injs: Injs) -> Option<E::Injection> {
+        injs.uninject().ok().map(Tagged::untag)
+    }
+}
+
+pub fn get_inj<E, Injs, Index>(injs: Injs, _marker: PhantomData<E>) -> Option<E::Injection>
+where
+    E: ResumeEffect<Injs, Index>,
+{
+    E::from_injs(injs)
+}
+
+/// The effect that `?` throws errors with in an effectful function. Only `std_effects::Throw` is,
+/// so that `?` isn't taken over by other effects that happen to be called `Throw`.
+#[diagnostic::on_unimplemented(
+    message = "`?` throws errors with `effing_mad::std_effects::Throw`, not `{Self}`",
+    label = "this is a different effect called `Throw`",
+    note = "write this effect with its module, e.g. `errors::Throw<E>`, and `?` is left alone"
+)]
+pub trait Thrown: Effect {
+    type Error;
+    fn throw(error: Self::Error) -> Self;
+}
+
+impl<E> Thrown for Throw<E> {
+    type Error = E;
+
+    fn throw(error: E) -> Self {
+        Throw(error)
+    }
+}
+
+/// Where `?` goes in an effectful function that throws `E`.
+pub enum Branch<T, E, R> {
+    Continue(T),
+    Throw(E),
+    /// Returns from the function, like the usual `?`.
+    Return(R),
+}
+
+/// The types that `?` works on in an effectful function that throws `E`. Errors in a `Result` are
+/// thrown, but `?` on an `Option` still returns `None` from the function.
+#[diagnostic::on_unimplemented(
+    message = "`?` in a function that throws only works on `Result` and `Option`, not `{Self}`"
+)]
+pub trait TryThrow<E> {
+    type Output;
+    type Residual;
+    fn branch(self) -> Branch<Self::Output, E, Self::Residual>;
+}
+
+impl<T, F, E: From<F>> TryThrow<E> for Result<T, F> {
+    type Output = T;
+    type Residual = Never;
+
+    fn branch(self) -> Branch<T, E, Never> {
+        match self {
+            Ok(v) => Branch::Continue(v),
+            Err(err) => Branch::Throw(E::from(err)),
+        }
+    }
+}
+
+impl<T, E> TryThrow<E> for Option<T> {
+    type Output = T;
+    type Residual = ReturnNone;
+
+    fn branch(self) -> Branch<T, E, ReturnNone> {
+        match self {
+            Some(v) => Branch::Continue(v),
+            None => Branch::Return(ReturnNone),
+        }
+    }
+}
+
+/// What `?` on a `None` returns from an effectful function.
+pub struct ReturnNone;
+
+/// What `?` returns from an effectful function whose return type is `R`.
+#[diagnostic::on_unimplemented(
+    message = "`?` on an `Option` can only be used in a function that returns an `Option`"
+)]
+pub trait Residual<R> {
+    fn into_return(self) -> R;
+}
+
+impl<R> Residual<R> for Never {
+    fn into_return(self) -> R {
+        match self {}
+    }
+}
+
+impl<T> Residual<Option<T>> for ReturnNone {
+    fn into_return(self) -> Option<T> {
+        None
+    }
 }
 
-pub fn get_inj2<E, I, Injs>(injs: Injs, _marker: PhantomData<I>) -> Option<I::Injection>
+/// Fixes the effects of a computation made by `effectful_expr!`, which unlike an effectful function
+/// has no return type to do it.
+pub fn with_effects<Effs, G>(g: G) -> G
 where
-    I: IntoEffect<Effect = E>,
-    E: Effect<Injection = Injs>,
+    Effs: InjectionList,
+    G: Generator<Effs::List, Yield = Effs>,
 {
-    I::uninject(injs)
+    g
 }
 
 pub trait EffectSet<Tail> {
@@ -48,3 +276,265 @@ impl<Tail> Prepend<Tail> for CNil {
 impl<Head, Tail1: Prepend<Tail2>, Tail2> Prepend<Tail2> for Coproduct<Head, Tail1> {
     type Out = Coproduct<Head, Tail1::Out>;
 }
+
+/// An index for [`EmbedEffs`] between coproducts of concrete types, which are embedded using one
+/// [`HasEffect`] index for each effect.
+pub struct Concrete<Indices>(PhantomData<Indices>);
+
+/// An index for [`EmbedEffs`] between an open row of effects and a coproduct that has the row as
+/// its tail, `Depth` types from the front.
+pub struct Row<Depth>(PhantomData<Depth>);
+
+/// Embeds the effects of a computation run with `do_` into those of the effectful function running
+/// it, and gets the computation's injections back out of the function's. Unlike frunk's
+/// `CoproductEmbedder`, this also works for functions with an open row of effects, like
+/// `#[effectful(A, ..E)]`. Inside such a function the row is an unknown type, so frunk can't embed
+/// it, but it can still be put in place as the tail.
+pub trait EmbedEffs<Out: InjectionList, Index>: InjectionList {
+    fn embed_effs(self) -> Out;
+    /// Handlers only ever resume a computation with the injection for the effect it yielded, which
+    /// is always one of `Self`'s, so there is nothing else to give back.
+    fn subset_injs(injs: Out::List) -> Self::List;
+}
+
+impl<Out: InjectionList> EmbedEffs<Out, Concrete<HNil>> for CNil {
+    fn embed_effs(self) -> Out {
+        match self {}
+    }
+
+    fn subset_injs(injs: Out::List) -> Self::List {
+        match injs.uninject() {
+            Ok(begin) => Coproduct::Inl(begin),
+            Err(_) => wrong_injection(),
+        }
+    }
+}
+
+impl<Head, Tail, Out, HeadIndex, TailIndices>
+    EmbedEffs<Out, Concrete<HCons<HeadIndex, TailIndices>>> for Coproduct<Head, Tail>
+where
+    Head: Effect,
+    Tail: EmbedEffs<Out, Concrete<TailIndices>>,
+    Out: HasEffect<Head, HeadIndex>,
+{
+    fn embed_effs(self) -> Out {
+        match self {
+            Coproduct::Inl(head) => Out::inject_effect(head),
+            Coproduct::Inr(tail) => tail.embed_effs(),
+        }
+    }
+
+    fn subset_injs(injs: Out::List) -> Self::List {
+        match Out::take_injection(injs) {
+            Ok(inj) => Coproduct::Inl(Tagged::new(inj)),
+            Err(injs) => Coproduct::Inr(Tail::subset_injs(injs)),
+        }
+    }
+}
+
+impl<Effs, Out, Depth> EmbedEffs<Out, Row<Depth>> for Effs
+where
+    Effs: InjectionList,
+    Out: InjectionList + EmbedTail<Effs, Depth>,
+{
+    fn embed_effs(self) -> Out {
+        Out::embed_tail(self)
+    }
+
+    fn subset_injs(injs: Out::List) -> Self::List {
+        Out::subset_tail(injs)
+    }
+}
+
+/// Implemented by sets of effects that include `E`. This is frunk's `CoprodInjector` and
+/// `CoprodUninjector` in one, but with an error message that makes sense when `do_` is used on a
+/// computation with too many effects.
+#[diagnostic::on_unimplemented(
+    message = "`do_` can't pass on the `{E}` effect, since the function using it doesn't have it",
+    label = "`{E}` is missing from this function's effects",
+    note = "either add `{E}` to the function's `#[effectful(...)]` list, or handle it before `do_`"
+)]
+pub trait HasEffect<E: Effect, Index>: InjectionList {
+    fn inject_effect(effect: E) -> Self;
+    /// Gives back the injections unchanged if they aren't for `E`.
+    fn take_injection(injs: Self::List) -> Result<E::Injection, Self::List>;
+}
+
+impl<E: Effect, Tail: InjectionList> HasEffect<E, Here> for Coproduct<E, Tail> {
+    fn inject_effect(effect: E) -> Self {
+        Coproduct::Inl(effect)
+    }
+
+    fn take_injection(injs: Self::List) -> Result<E::Injection, Self::List> {
+        match injs {
+            Coproduct::Inl(inj) => Ok(inj.untag()),
+            Coproduct::Inr(tail) => Err(Coproduct::Inr(tail)),
+        }
+    }
+}
+
+impl<Head, Tail, E, Index> HasEffect<E, There<Index>> for Coproduct<Head, Tail>
+where
+    Head: Effect,
+    Tail: HasEffect<E, Index>,
+    E: Effect,
+{
+    fn inject_effect(effect: E) -> Self {
+        Coproduct::Inr(Tail::inject_effect(effect))
+    }
+
+    fn take_injection(injs: Self::List) -> Result<E::Injection, Self::List> {
+        match injs {
+            Coproduct::Inl(inj) => Err(Coproduct::Inl(inj)),
+            Coproduct::Inr(tail) => Tail::take_injection(tail).map_err(Coproduct::Inr),
+        }
+    }
+}
+
+/// What `do_` needs to run a computation with effects `Self` inside an effectful function with
+/// effects `Caller`. The method is generic rather than the trait so that `do_` can name the index,
+/// since functions without a row of effects only use the `Concrete` indices.
+pub trait DoEffects: InjectionList + Sized {
+    /// Embeds the effects into the caller's, along with the function that gets the computation's
+    /// injections back out of the caller's.
+    fn do_embed<Caller, Index>(self) -> (Caller, SubsetInjs<Self, Caller>)
+    where
+        Caller: InjectionList,
+        Self: EmbedEffs<Caller, Index>,
+    {
+        (self.embed_effs(), Self::subset_injs)
+    }
+}
+
+impl<Effs: InjectionList> DoEffects for Effs {}
+
+/// Gets the injections of a computation run with `do_` back out of those of the function running
+/// it.
+pub type SubsetInjs<Effs, Caller> =
+    fn(<Caller as InjectionList>::List) -> <Effs as InjectionList>::List;
+
+// handlers always resume with the injection for the effect that was yielded, so this only happens
+// if a computation is resumed by hand with the wrong one
+#[cold]
+fn wrong_injection() -> ! {
+    panic!("an effectful computation was resumed with the injection for an effect it didn't yield")
+}
+
+/// Puts a row of effects in place as the tail of `Self`.
+pub trait EmbedTail<Effs: InjectionList, Depth>: InjectionList {
+    fn embed_tail(effs: Effs) -> Self;
+    fn subset_tail(injs: Self::List) -> Effs::List;
+}
+
+impl<Head: Effect, Tail: InjectionList> EmbedTail<Tail, Here> for Coproduct<Head, Tail> {
+    fn embed_tail(effs: Tail) -> Self {
+        Coproduct::Inr(effs)
+    }
+
+    fn subset_tail(injs: Self::List) -> Tail::List {
+        match injs {
+            Coproduct::Inl(_) => wrong_injection(),
+            Coproduct::Inr(tail) => tail,
+        }
+    }
+}
+
+impl<Head, Tail, Effs, Depth> EmbedTail<Effs, There<Depth>> for Coproduct<Head, Tail>
+where
+    Head: Effect,
+    Tail: EmbedTail<Effs, Depth>,
+    Effs: InjectionList,
+{
+    fn embed_tail(effs: Effs) -> Self {
+        Coproduct::Inr(Tail::embed_tail(effs))
+    }
+
+    fn subset_tail(injs: Self::List) -> Effs::List {
+        match injs {
+            Coproduct::Inl(_) => wrong_injection(),
+            Coproduct::Inr(tail) => Tail::subset_tail(tail),
+        }
+    }
+}
+
+/// The operations of a group that `handler!` has no arm for, so that the error for a missing one
+/// can name it. The generated code panics with the message, which makes it a compile error.
+pub struct MissingOperations {
+    msg: [u8; 512],
+    len: usize,
+    any: bool,
+}
+
+impl MissingOperations {
+    /// Compares the group's `operations`, from its module, with the ones `handled` by the arms
+    pub const fn new(group: &str, operations: &[&str], handled: &[&str]) -> Self {
+        let mut msg = [0; 512];
+        let mut len = push_str(&mut msg, 0, "handler for `");
+        len = push_str(&mut msg, len, group);
+        len = push_str(&mut msg, len, "` is missing operations: ");
+        let start = len;
+
+        let mut i = 0;
+        while i < operations.len() {
+            let mut j = 0;
+            while j < handled.len() && !str_eq(operations[i], handled[j]) {
+                j += 1;
+            }
+            if j == handled.len() {
+                if len > start {
+                    len = push_str(&mut msg, len, ", ");
+                }
+                len = push_str(&mut msg, len, "`");
+                len = push_str(&mut msg, len, operations[i]);
+                len = push_str(&mut msg, len, "`");
+            }
+            i += 1;
+        }
+
+        MissingOperations {
+            msg,
+            len,
+            any: len > start,
+        }
+    }
+
+    pub const fn message(&self) -> Option<&str> {
+        if !self.any {
+            return None;
+        }
+        let (msg, _) = self.msg.split_at(self.len);
+        match core::str::from_utf8(msg) {
+            Ok(msg) => Some(msg),
+            Err(_) => Some("handler is missing operations"),
+        }
+    }
+}
+
+/// Appends `s` to the first `len` bytes of `buf`, leaving it out if it doesn't fit
+const fn push_str(buf: &mut [u8], len: usize, s: &str) -> usize {
+    let s = s.as_bytes();
+    if len + s.len() > buf.len() {
+        return len;
+    }
+    let mut i = 0;
+    while i < s.len() {
+        buf[len + i] = s[i];
+        i += 1;
+    }
+    len + s.len()
+}
+
+const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
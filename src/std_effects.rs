@@ -0,0 +1,432 @@
+//! Ready-made definitions of commonly used effects, along with handlers for them. Using these
+//! instead of defining your own means that effectful functions from different crates can share
+//! effects, and so be composed with each other.
+//!
+//! Each `run_*` function handles one effect and returns a generator for the remaining effects, just
+//! like [`handle`](crate::handle). The exceptions are [`run_choose`], as exploring every branch of a
+//! computation needs [`handle_multishot`](crate::handle_multishot), and [`run_await`], as waiting
+//! for futures needs to happen in an async function.
+
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+#[cfg(feature = "alloc")]
+use core::{future::Future, pin::Pin};
+use core::{marker::PhantomData, ops::ControlFlow};
+
+use frunk::coproduct::{CoprodInjector, CoprodUninjector, CoproductEmbedder};
+
+use crate::{
+    generator::Generator,
+    handle, handle_multishot_with_return, handle_with_return,
+    injection::{Begin, InjectionList, Tagged},
+    Effect, Handler, Never,
+};
+
+crate::effects! {
+    /// Mutable state of type `S`, which can be read with `State::get()` and replaced with
+    /// `State::put(v)`.
+    pub state::State<S> {
+        fn get() -> S;
+        fn put(v: S);
+    }
+}
+
+pub use state::State;
+
+/// Read access to an environment of type `R`, using `Reader::ask()`.
+pub struct Reader<R>(PhantomData<R>);
+
+impl<R> Reader<R> {
+    pub fn ask() -> Self {
+        Reader(PhantomData)
+    }
+}
+
+impl<R> Effect for Reader<R> {
+    type Injection = R;
+}
+
+/// Append-only output of values of type `W`, using `Writer::tell(w)`.
+pub struct Writer<W>(pub W);
+
+impl<W> Writer<W> {
+    pub fn tell(w: W) -> Self {
+        Writer(w)
+    }
+}
+
+impl<W> Effect for Writer<W> {
+    type Injection = ();
+}
+
+/// Failure with an error of type `E`, using `yield Throw(e)`. A computation can't be resumed after
+/// it throws.
+///
+/// In `#[effectful]` functions with a `Throw<E>` effect, `?` on a `Result` throws the error
+/// (converted with `From`, like the usual `?`) instead of returning it. `?` on an `Option` still
+/// returns `None`. Other effects called `Throw` have to be written with their module, as in
+/// `#[effectful(errors::Throw<E>)]`, for `?` to keep its usual meaning.
+pub struct Throw<E>(pub E);
+
+impl<E> Effect for Throw<E> {
+    type Injection = Never;
+}
+
+/// Another name for [`Throw`], which is handled by [`run_exception`] as well as [`catch`]. `?` only
+/// throws in functions that list the effect as `Throw<E>`.
+pub type Exception<E> = Throw<E>;
+
+/// Stopping a computation early, without an error. A computation can't be resumed after it
+/// cancels.
+pub struct Cancel;
+
+impl Effect for Cancel {
+    type Injection = Never;
+}
+
+/// A nondeterministic choice between `true` and `false`.
+pub struct Choose;
+
+impl Effect for Choose {
+    type Injection = bool;
+}
+
+/// Generation of a fresh ID, which is different to every other ID generated by the same handler.
+pub struct Fresh;
+
+impl Effect for Fresh {
+    type Injection = u64;
+}
+
+/// Generation of a random number.
+pub struct Random;
+
+impl Effect for Random {
+    type Injection = u64;
+}
+
+/// Waiting for a future to complete, using `yield Await::new(future)`. This lets effectful code do
+/// async work without being async itself, leaving it up to an async handler such as
+/// [`await_future`] to actually wait.
+///
+/// By default the future is boxed as a `dyn Future + Send`, so that the computation waiting for it
+/// can still be sent to other threads. Futures that aren't `Send` can be awaited with
+/// [`LocalAwait`], and `F` can also be a future type of your own.
+#[cfg(feature = "alloc")]
+pub struct Await<T, F = dyn Future<Output = T> + Send>(pub Pin<Box<F>>)
+where
+    F: Future<Output = T> + ?Sized;
+
+/// Waiting for a future that isn't `Send`, using `yield Await::local(future)`.
+#[cfg(feature = "alloc")]
+pub type LocalAwait<T> = Await<T, dyn Future<Output = T>>;
+
+#[cfg(feature = "alloc")]
+impl<T> Await<T> {
+    pub fn new(future: impl Future<Output = T> + Send + 'static) -> Self {
+        Await(Box::pin(future))
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T> LocalAwait<T> {
+    pub fn local(future: impl Future<Output = T> + 'static) -> Self {
+        Await(Box::pin(future))
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T, F: Future<Output = T> + ?Sized> Effect for Await<T, F> {
+    type Injection = T;
+}
+
+crate::handler! {
+    struct StateHandler<S: Clone> { state: S } for state::State<S>,
+    get() => ControlFlow::Continue(self.state.clone()),
+    put(v) => {
+        self.state = v;
+        ControlFlow::Continue(())
+    },
+    return(ret) -> (R, S) => (ret, self.state.clone()),
+}
+
+/// Handle `State<S>`, starting from `init`. The handled computation also returns the final state.
+pub fn run_state<
+    G,
+    R,
+    S,
+    PreEs,
+    PostEs,
+    EffIndex,
+    PreIs,
+    PostIs,
+    BeginIndex,
+    InjIndex,
+    EmbedIndices,
+>(
+    g: G,
+    init: S,
+) -> impl Generator<PostIs, Yield = PostEs, Return = (R, S)>
+where
+    S: Clone,
+    PreEs: InjectionList<List = PreIs> + CoprodUninjector<State<S>, EffIndex, Remainder = PostEs>,
+    PostEs: InjectionList<List = PostIs>,
+    PreIs: CoprodInjector<Begin, BeginIndex>
+        + CoprodInjector<Tagged<state::StateInjs<S>, State<S>>, InjIndex>,
+    PostIs: CoproductEmbedder<PreIs, EmbedIndices>,
+    G: Generator<PreIs, Yield = PreEs, Return = R>,
+{
+    handle(g, StateHandler { state: init })
+}
+
+/// Handle `Reader<Env>` by giving out clones of `env`.
+pub fn run_reader<
+    G,
+    R,
+    Env,
+    PreEs,
+    PostEs,
+    EffIndex,
+    PreIs,
+    PostIs,
+    BeginIndex,
+    InjIndex,
+    EmbedIndices,
+>(
+    g: G,
+    env: Env,
+) -> impl Generator<PostIs, Yield = PostEs, Return = R>
+where
+    Env: Clone,
+    PreEs:
+        InjectionList<List = PreIs> + CoprodUninjector<Reader<Env>, EffIndex, Remainder = PostEs>,
+    PostEs: InjectionList<List = PostIs>,
+    PreIs: CoprodInjector<Begin, BeginIndex> + CoprodInjector<Tagged<Env, Reader<Env>>, InjIndex>,
+    PostIs: CoproductEmbedder<PreIs, EmbedIndices>,
+    G: Generator<PreIs, Yield = PreEs, Return = R>,
+{
+    handle(g, move |_: Reader<Env>| ControlFlow::Continue(env.clone()))
+}
+
+struct WriterHandler<O>(O);
+
+impl<W, R, O: Default + Extend<W>> Handler<Writer<W>, R> for WriterHandler<O> {
+    type Output = (R, O);
+
+    fn handle(&mut self, Writer(w): Writer<W>) -> ControlFlow<(R, O), ()> {
+        self.0.extend(core::iter::once(w));
+        ControlFlow::Continue(())
+    }
+    fn on_return(&mut self, ret: R) -> (R, O) {
+        (ret, core::mem::take(&mut self.0))
+    }
+}
+
+/// Handle `Writer<W>` by collecting everything written into `O`, which could be a `Vec<W>` or, for
+/// example, a `String` if `W` is `char` or `&str`. The handled computation also returns the
+/// collected output.
+pub fn run_writer<
+    G,
+    R,
+    W,
+    O,
+    PreEs,
+    PostEs,
+    EffIndex,
+    PreIs,
+    PostIs,
+    BeginIndex,
+    InjIndex,
+    EmbedIndices,
+>(
+    g: G,
+) -> impl Generator<PostIs, Yield = PostEs, Return = (R, O)>
+where
+    O: Default + Extend<W>,
+    PreEs: InjectionList<List = PreIs> + CoprodUninjector<Writer<W>, EffIndex, Remainder = PostEs>,
+    PostEs: InjectionList<List = PostIs>,
+    PreIs: CoprodInjector<Begin, BeginIndex> + CoprodInjector<Tagged<(), Writer<W>>, InjIndex>,
+    PostIs: CoproductEmbedder<PreIs, EmbedIndices>,
+    G: Generator<PreIs, Yield = PreEs, Return = R>,
+{
+    handle(g, WriterHandler(O::default()))
+}
+
+/// Handle `Throw<E>`. The handled computation returns `Err` with the error if one was thrown, or
+/// `Ok` with its usual return value otherwise.
+pub fn catch<G, R, E, PreEs, PostEs, EffIndex, PreIs, PostIs, BeginIndex, InjIndex, EmbedIndices>(
+    g: G,
+) -> impl Generator<PostIs, Yield = PostEs, Return = Result<R, E>>
+where
+    PreEs: InjectionList<List = PreIs> + CoprodUninjector<Throw<E>, EffIndex, Remainder = PostEs>,
+    PostEs: InjectionList<List = PostIs>,
+    PreIs: CoprodInjector<Begin, BeginIndex> + CoprodInjector<Tagged<Never, Throw<E>>, InjIndex>,
+    PostIs: CoproductEmbedder<PreIs, EmbedIndices>,
+    G: Generator<PreIs, Yield = PreEs, Return = R>,
+{
+    handle_with_return(g, |Throw(e)| ControlFlow::Break(Err(e)), Ok)
+}
+
+/// Handle `Exception<E>`, which is the same as [`catch`].
+pub fn run_exception<
+    G,
+    R,
+    E,
+    PreEs,
+    PostEs,
+    EffIndex,
+    PreIs,
+    PostIs,
+    BeginIndex,
+    InjIndex,
+    EmbedIndices,
+>(
+    g: G,
+) -> impl Generator<PostIs, Yield = PostEs, Return = Result<R, E>>
+where
+    PreEs:
+        InjectionList<List = PreIs> + CoprodUninjector<Exception<E>, EffIndex, Remainder = PostEs>,
+    PostEs: InjectionList<List = PostIs>,
+    PreIs:
+        CoprodInjector<Begin, BeginIndex> + CoprodInjector<Tagged<Never, Exception<E>>, InjIndex>,
+    PostIs: CoproductEmbedder<PreIs, EmbedIndices>,
+    G: Generator<PreIs, Yield = PreEs, Return = R>,
+{
+    catch(g)
+}
+
+/// Handle `Cancel`. The handled computation returns `None` if it was cancelled.
+pub fn run_cancel<
+    G,
+    R,
+    PreEs,
+    PostEs,
+    EffIndex,
+    PreIs,
+    PostIs,
+    BeginIndex,
+    InjIndex,
+    EmbedIndices,
+>(
+    g: G,
+) -> impl Generator<PostIs, Yield = PostEs, Return = Option<R>>
+where
+    PreEs: InjectionList<List = PreIs> + CoprodUninjector<Cancel, EffIndex, Remainder = PostEs>,
+    PostEs: InjectionList<List = PostIs>,
+    PreIs: CoprodInjector<Begin, BeginIndex> + CoprodInjector<Tagged<Never, Cancel>, InjIndex>,
+    PostIs: CoproductEmbedder<PreIs, EmbedIndices>,
+    G: Generator<PreIs, Yield = PreEs, Return = R>,
+{
+    handle_with_return(g, |Cancel| ControlFlow::Break(None), Some)
+}
+
+/// Run a computation whose only effect is `Choose`, taking every possible path through it, and
+/// collect the results from each path into `C`. Paths where `true` is chosen come first.
+///
+/// The generator must be `Clone` so that it can be resumed more than once - see
+/// [`handle_multishot`](crate::handle_multishot).
+pub fn run_choose<G, R, C>(g: G) -> C
+where
+    G: Generator<
+            frunk::Coprod!(Tagged<bool, Choose>, Begin),
+            Yield = frunk::Coprod!(Choose),
+            Return = R,
+        > + Clone,
+    C: Default + Extend<R> + IntoIterator<Item = R>,
+{
+    handle_multishot_with_return(
+        g,
+        |Choose, k| {
+            let mut results: C = k.resume(true);
+            results.extend(k.resume(false));
+            results
+        },
+        |ret| {
+            let mut results = C::default();
+            results.extend(core::iter::once(ret));
+            results
+        },
+    )
+}
+
+/// Handle `Fresh` by counting up from 0.
+pub fn run_fresh<G, R, PreEs, PostEs, EffIndex, PreIs, PostIs, BeginIndex, InjIndex, EmbedIndices>(
+    g: G,
+) -> impl Generator<PostIs, Yield = PostEs, Return = R>
+where
+    PreEs: InjectionList<List = PreIs> + CoprodUninjector<Fresh, EffIndex, Remainder = PostEs>,
+    PostEs: InjectionList<List = PostIs>,
+    PreIs: CoprodInjector<Begin, BeginIndex> + CoprodInjector<Tagged<u64, Fresh>, InjIndex>,
+    PostIs: CoproductEmbedder<PreIs, EmbedIndices>,
+    G: Generator<PreIs, Yield = PreEs, Return = R>,
+{
+    let mut next = 0;
+    handle(g, move |Fresh| {
+        let id = next;
+        next += 1;
+        ControlFlow::Continue(id)
+    })
+}
+
+/// Handle `Random` using a pseudorandom number generator (xorshift64) seeded with `seed`. The same
+/// seed always gives the same sequence of numbers, so this is not suitable for cryptography.
+pub fn run_random<
+    G,
+    R,
+    PreEs,
+    PostEs,
+    EffIndex,
+    PreIs,
+    PostIs,
+    BeginIndex,
+    InjIndex,
+    EmbedIndices,
+>(
+    g: G,
+    seed: u64,
+) -> impl Generator<PostIs, Yield = PostEs, Return = R>
+where
+    PreEs: InjectionList<List = PreIs> + CoprodUninjector<Random, EffIndex, Remainder = PostEs>,
+    PostEs: InjectionList<List = PostIs>,
+    PreIs: CoprodInjector<Begin, BeginIndex> + CoprodInjector<Tagged<u64, Random>, InjIndex>,
+    PostIs: CoproductEmbedder<PreIs, EmbedIndices>,
+    G: Generator<PreIs, Yield = PreEs, Return = R>,
+{
+    // xorshift gets stuck at 0
+    let mut state = if seed == 0 {
+        0x9e37_79b9_7f4a_7c15
+    } else {
+        seed
+    };
+    handle(g, move |Random| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        ControlFlow::Continue(state)
+    })
+}
+
+/// An async handler for `Await<T, F>`, which waits for the future and resumes the computation with
+/// its output. This can go in the list of handlers passed to
+/// [`run_async_many`](crate::run_async_many), for computations that do other things as well.
+#[cfg(feature = "alloc")]
+pub async fn await_future<T, F, R>(Await(future): Await<T, F>) -> ControlFlow<R, T>
+where
+    F: Future<Output = T> + ?Sized,
+{
+    ControlFlow::Continue(future.await)
+}
+
+/// Run a computation whose only effect is `Await<T, F>`, waiting for each future it yields in turn.
+#[cfg(feature = "alloc")]
+pub async fn run_await<G, R, T, F>(g: G) -> R
+where
+    F: Future<Output = T> + ?Sized,
+    G: Generator<
+        frunk::Coprod!(Tagged<T, Await<T, F>>, Begin),
+        Yield = frunk::Coprod!(Await<T, F>),
+        Return = R,
+    >,
+{
+    crate::run_async(g, await_future).await
+}
@@ -0,0 +1,217 @@
+//! Running effectful computations as asynchronous streams of effects.
+//!
+//! [`into_stream`] hands each effect a computation performs to whoever is polling the stream,
+//! along with a [`Responder`] that resumes the computation. The consumer can answer effects
+//! however it likes, for example by sending them over a channel to another task, and the stream
+//! waits until it does.
+
+use alloc::boxed::Box;
+use core::{
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use frunk::{
+    coproduct::{CNil, CoprodInjector},
+    indices::{Here, There},
+    Coproduct,
+};
+use futures_channel::oneshot;
+use futures_core::{FusedStream, Stream};
+
+use crate::{
+    generator::{Generator, GeneratorState},
+    injection::{Begin, InjectionList, Tagged},
+    Effect,
+};
+
+/// Turn an effectful computation into a stream of the effects it performs. Each item comes with a
+/// [`Responder`], which must be used to resume the computation before the stream yields anything
+/// else. If a responder is dropped without being used, the stream ends and the computation is
+/// dropped.
+///
+/// The stream also ends when the computation returns. Its return value can then be taken with
+/// [`EffectStream::into_return`].
+pub fn into_stream<G, Effs>(g: G) -> EffectStream<G, Effs>
+where
+    Effs: InjectionList + Which,
+    G: Generator<Effs::List, Yield = Effs>,
+{
+    EffectStream {
+        gen: Box::pin(g),
+        state: State::Resume(CoprodInjector::inject(Begin)),
+        ret: None,
+        _effs: PhantomData,
+    }
+}
+
+enum State<Injs> {
+    Resume(Injs),
+    Waiting(oneshot::Receiver<Injs>),
+    Finished,
+}
+
+/// The stream returned by [`into_stream`].
+pub struct EffectStream<G, Effs>
+where
+    Effs: InjectionList,
+    G: Generator<Effs::List, Yield = Effs>,
+{
+    gen: Pin<Box<G>>,
+    state: State<Effs::List>,
+    ret: Option<G::Return>,
+    _effs: PhantomData<fn() -> Effs>,
+}
+
+// the computation is boxed, and nothing else is ever pinned
+impl<G, Effs> Unpin for EffectStream<G, Effs>
+where
+    Effs: InjectionList,
+    G: Generator<Effs::List, Yield = Effs>,
+{
+}
+
+impl<G, Effs> EffectStream<G, Effs>
+where
+    Effs: InjectionList,
+    G: Generator<Effs::List, Yield = Effs>,
+{
+    /// The value the computation returned, if the stream ended because it returned.
+    pub fn into_return(self) -> Option<G::Return> {
+        self.ret
+    }
+}
+
+impl<G, Effs> Stream for EffectStream<G, Effs>
+where
+    Effs: InjectionList + Which,
+    G: Generator<Effs::List, Yield = Effs>,
+{
+    type Item = (Effs, Responder<Effs>);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let injs = match mem::replace(&mut this.state, State::Finished) {
+            State::Resume(injs) => injs,
+            State::Waiting(mut responded) => match Pin::new(&mut responded).poll(cx) {
+                Poll::Ready(Ok(injs)) => injs,
+                Poll::Ready(Err(oneshot::Canceled)) => return Poll::Ready(None),
+                Poll::Pending => {
+                    this.state = State::Waiting(responded);
+                    return Poll::Pending;
+                }
+            },
+            State::Finished => return Poll::Ready(None),
+        };
+        match this.gen.as_mut().resume(injs) {
+            GeneratorState::Yielded(effs) => {
+                let (sender, responded) = oneshot::channel();
+                this.state = State::Waiting(responded);
+                let responder = Responder {
+                    sender,
+                    effect: effs.which(),
+                };
+                Poll::Ready(Some((effs, responder)))
+            }
+            GeneratorState::Complete(ret) => {
+                this.ret = Some(ret);
+                Poll::Ready(None)
+            }
+        }
+    }
+}
+
+impl<G, Effs> FusedStream for EffectStream<G, Effs>
+where
+    Effs: InjectionList + Which,
+    G: Generator<Effs::List, Yield = Effs>,
+{
+    fn is_terminated(&self) -> bool {
+        matches!(self.state, State::Finished)
+    }
+}
+
+/// Resumes a computation run by an [`EffectStream`] with the injection for one of its effects.
+/// This can be sent to other tasks or threads, as long as the injections can.
+pub struct Responder<Effs: InjectionList> {
+    sender: oneshot::Sender<Effs::List>,
+    // the position of the effect this responder came with in `Effs`
+    effect: usize,
+}
+
+impl<Effs: InjectionList> Responder<Effs> {
+    /// Resume the computation with the injection for effect `E`, which must be the effect this
+    /// responder came with. If it isn't, the computation isn't resumed, and the responder and
+    /// injection are given back in the error. If the stream has already been dropped, this does
+    /// nothing.
+    pub fn respond<E, Index>(self, injection: E::Injection) -> Result<(), WrongEffect<Effs, E>>
+    where
+        E: Effect,
+        Effs::List: CoprodInjector<Tagged<E::Injection, E>, Index>,
+        Index: Position,
+    {
+        if Index::POSITION != self.effect {
+            return Err(WrongEffect {
+                responder: self,
+                injection,
+            });
+        }
+        // an error just means nobody is listening any more
+        let _ = self
+            .sender
+            .send(CoprodInjector::inject(Tagged::new(injection)));
+        Ok(())
+    }
+}
+
+/// The error from [`Responder::respond`] when it's given the injection for effect `E`, but the
+/// responder came with a different effect.
+pub struct WrongEffect<Effs: InjectionList, E: Effect> {
+    /// The responder, which can still resume the computation with the right injection.
+    pub responder: Responder<Effs>,
+    pub injection: E::Injection,
+}
+
+impl<Effs: InjectionList, E: Effect> fmt::Debug for WrongEffect<Effs, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WrongEffect").finish_non_exhaustive()
+    }
+}
+
+/// Coproducts of effects that know which of their effects they hold, so that a [`Responder`] can
+/// check it's given the injection for that one.
+pub trait Which {
+    /// The position of the effect, counting from 0 at the front.
+    fn which(&self) -> usize;
+}
+
+impl Which for CNil {
+    fn which(&self) -> usize {
+        match *self {}
+    }
+}
+
+impl<E, Tail: Which> Which for Coproduct<E, Tail> {
+    fn which(&self) -> usize {
+        match self {
+            Coproduct::Inl(_) => 0,
+            Coproduct::Inr(tail) => 1 + tail.which(),
+        }
+    }
+}
+
+/// The position in a coproduct that a frunk index points to, counting from 0 at the front.
+pub trait Position {
+    const POSITION: usize;
+}
+
+impl Position for Here {
+    const POSITION: usize = 0;
+}
+
+impl<Index: Position> Position for There<Index> {
+    const POSITION: usize = 1 + Index::POSITION;
+}
@@ -0,0 +1,252 @@
+//! What `#[effectful]` and `effectful_expr!` accept, and how they lower the body: `yield`s and
+//! `.do_`s nested anywhere in it, methods, where clauses, borrowed arguments, closures and `?`.
+
+#![cfg_attr(feature = "nightly", feature(generators))]
+#![cfg_attr(feature = "nightly", feature(generator_trait))]
+
+use core::{num::ParseIntError, ops::ControlFlow};
+
+use effing_mad::{
+    effectful, effectful_expr,
+    functor_eff::IntoEffectful,
+    handle, run,
+    std_effects::{catch, Throw},
+    Effect,
+};
+
+struct Ask;
+
+impl Effect for Ask {
+    type Injection = i32;
+}
+
+struct Log(String);
+
+impl Effect for Log {
+    type Injection = ();
+}
+
+#[effectful(Ask)]
+fn ask_twice() -> i32 {
+    let a = yield_!(Ask);
+    a + yield_!(Ask)
+}
+
+#[effectful(Ask)]
+fn nested() -> i32 {
+    let mut total = 0;
+    for i in 0..3 {
+        total += match i {
+            0 => yield_!(Ask),
+            _ => ask_twice().do_,
+        };
+    }
+    total
+}
+
+// both of these start a statement, which they still have to once lowered
+#[effectful(Ask)]
+fn leading() -> i32 {
+    let doubled = { yield_!(Ask) * 2 };
+    ask_twice().do_ * 10 + doubled
+}
+
+#[effectful(Ask)]
+fn scaled<T>(x: T) -> T
+where
+    T: core::ops::Mul<i32, Output = T>,
+{
+    x * yield_!(Ask)
+}
+
+#[effectful(Log)]
+fn log_prefixed(
+    prefix: &str,
+    lines: impl Iterator<Item = &str>,
+    format: &dyn Fn(&str, &str) -> String,
+) -> usize {
+    let mut count = 0;
+    for line in lines {
+        yield_!(Log(format(prefix, line)));
+        count += 1;
+    }
+    count
+}
+
+trait Repo {
+    #[effectful(Log)]
+    fn load(&self, id: usize) -> String;
+}
+
+struct Users(Vec<&'static str>);
+
+impl Repo for Users {
+    #[effectful(Log)]
+    fn load(&self, id: usize) -> String {
+        yield_!(Log(format!("loading user {id}")));
+        self.0[id].into()
+    }
+}
+
+impl Users {
+    // borrows both `self` and `name`, for two unrelated lifetimes
+    #[effectful(Log)]
+    fn find(&self, name: &str) -> Option<usize> {
+        yield_!(Log(format!("finding {name}")));
+        self.0.iter().position(|user| *user == name)
+    }
+}
+
+struct Counter(i32);
+
+impl Counter {
+    #[effectful(Ask)]
+    fn bump(&mut self) -> i32 {
+        let by = ask_twice().do_;
+        self.0 += by;
+        self.0
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum ParseError {
+    Empty,
+    Number(ParseIntError),
+}
+
+impl From<ParseIntError> for ParseError {
+    fn from(error: ParseIntError) -> Self {
+        ParseError::Number(error)
+    }
+}
+
+#[effectful(Throw<ParseError>)]
+fn parse_sum(a: &str, b: &str) -> i32 {
+    if a.is_empty() || b.is_empty() {
+        match yield_!(Throw(ParseError::Empty)) {}
+    }
+    a.parse::<i32>()? + b.parse::<i32>()?
+}
+
+// `?` on the `Option` returns `None`, and only `?` on the `Result` throws
+#[effectful(Throw<ParseError>)]
+fn first_number(s: &str) -> Option<i32> {
+    let first = s.split(',').next().filter(|first| !first.is_empty())?;
+    Some(first.parse::<i32>()?)
+}
+
+mod errors {
+    /// Not `std_effects::Throw`, so `?` is left alone in functions with this effect.
+    pub struct Throw<E>(pub E);
+
+    impl<E> effing_mad::Effect for Throw<E> {
+        type Injection = ();
+    }
+}
+
+#[effectful(errors::Throw<&'static str>)]
+fn parse_warned(s: &str) -> Result<i32, ParseIntError> {
+    yield_!(errors::Throw("parsing"));
+    let n: i32 = s.parse()?;
+    Ok(n * 2)
+}
+
+fn ask<R>(n: i32) -> impl FnMut(Ask) -> ControlFlow<R, i32> {
+    move |Ask| ControlFlow::Continue(n)
+}
+
+#[test]
+fn nested_yields_and_do() {
+    let mut asked = 0;
+    let handled = handle(nested(), |Ask| {
+        asked += 1;
+        ControlFlow::Continue(asked)
+    });
+    assert_eq!(run(handled), 1 + (2 + 3) + (4 + 5));
+
+    let mut asked = 0;
+    let handled = handle(leading(), |Ask| {
+        asked += 1;
+        ControlFlow::Continue(asked)
+    });
+    assert_eq!(run(handled), (2 + 3) * 10 + 2);
+}
+
+#[test]
+fn methods_and_where_clauses() {
+    assert_eq!(run(handle(scaled(3), ask(5))), 15);
+
+    let users = Users(vec!["alice", "bob"]);
+    let mut logs = Vec::new();
+    let loaded = handle(users.load(1), |Log(msg)| {
+        logs.push(msg);
+        ControlFlow::Continue(())
+    });
+    assert_eq!(run(loaded), "bob");
+    assert_eq!(logs, ["loading user 1"]);
+
+    let name = String::from("alice");
+    let found = handle(users.find(&name), |Log(_)| ControlFlow::Continue(()));
+    assert_eq!(run(found), Some(0));
+
+    let mut counter = Counter(1);
+    assert_eq!(run(handle(counter.bump(), ask(2))), 5);
+    assert_eq!(counter.0, 5);
+}
+
+#[test]
+fn capture_argument_lifetimes() {
+    let prefix = String::from("> ");
+    let lines = [String::from("one"), String::from("two")];
+    let format = |prefix: &str, line: &str| format!("{prefix}{line}");
+    let mut logged = Vec::new();
+    let lines = lines.iter().map(String::as_str);
+    let handled = handle(log_prefixed(&prefix, lines, &format), |Log(line)| {
+        logged.push(line);
+        ControlFlow::Continue(())
+    });
+    assert_eq!(run(handled), 2);
+    assert_eq!(logged, ["> one", "> two"]);
+}
+
+#[test]
+fn effectful_closures_and_blocks() {
+    let offset = 10;
+    let add_offset = effectful_expr!(Ask => |x: i32| -> i32 { x + offset + ask_twice().do_ });
+    let option = Some(1).into_effectful().map(add_offset);
+    assert_eq!(run(handle(option, ask(1))), Some(13));
+
+    let doubled = effectful_expr!(Ask =>
+        let n = yield_!(Ask);
+        n * 2
+    );
+    assert_eq!(run(handle(doubled, ask(21))), 42);
+}
+
+#[test]
+fn question_mark_throws() {
+    assert_eq!(run(catch(parse_sum("1", "2"))), Ok(3));
+    let thrown = run(catch(parse_sum("1", "two")));
+    assert!(matches!(thrown, Err(ParseError::Number(_))));
+    assert_eq!(run(catch(parse_sum("", "2"))), Err(ParseError::Empty));
+
+    // a thrown error can be recovered from by the handler
+    let recovered = handle(parse_sum("one", "2"), |Throw(_)| ControlFlow::Break(0));
+    assert_eq!(run(recovered), 0);
+
+    assert_eq!(run(catch(first_number("4,5"))), Ok(Some(4)));
+    assert_eq!(run(catch(first_number(""))), Ok(None));
+    assert!(matches!(
+        run(catch(first_number("four"))),
+        Err(ParseError::Number(_))
+    ));
+
+    let mut warnings = Vec::new();
+    let mut warned = |errors::Throw(warning)| {
+        warnings.push(warning);
+        ControlFlow::Continue(())
+    };
+    assert_eq!(run(handle(parse_warned("7"), &mut warned)), Ok(14));
+    assert!(run(handle(parse_warned("seven"), &mut warned)).is_err());
+    assert_eq!(warnings, ["parsing", "parsing"]);
+}
@@ -0,0 +1,191 @@
+//! Handlers implementing `Handler`, either by hand or through `handler!`, and the hooks they get
+//! when the computation returns or is finished with.
+
+#![cfg_attr(feature = "nightly", feature(generators))]
+#![cfg_attr(feature = "nightly", feature(generator_trait))]
+#![cfg_attr(feature = "nightly", feature(pin_macro))]
+
+use core::{cell::Cell, ops::ControlFlow, pin::pin};
+
+use effing_mad::{
+    effectful,
+    frunk::Coproduct,
+    generator::{Generator, GeneratorState},
+    handle, handler,
+    injection::Begin,
+    run, run_async, run_blocking,
+    std_effects::{state, State},
+    AsyncHandler, Effect, Handler,
+};
+
+struct Ask;
+
+impl Effect for Ask {
+    type Injection = i32;
+}
+
+struct Log(String);
+
+impl Effect for Log {
+    type Injection = ();
+}
+
+#[effectful(Ask, Log)]
+fn ask_and_log() -> i32 {
+    let a = yield_!(Ask);
+    yield_!(Log(format!("asked {a}")));
+    a + yield_!(Ask)
+}
+
+#[effectful(Ask)]
+fn ask_sum() -> i32 {
+    yield_!(Ask) + yield_!(Ask)
+}
+
+#[effectful(State<i32>)]
+fn tick() -> i32 {
+    let n = yield_!(State::get());
+    yield_!(State::put(n + 1));
+    n
+}
+
+/// Answers every `Ask` with `answer`, or breaks out with -1 if it's `None`, and counts how many
+/// times it has been finished with.
+struct Answer<'a> {
+    answer: Option<i32>,
+    dropped: &'a Cell<u32>,
+}
+
+impl Handler<Ask, i32> for Answer<'_> {
+    type Output = i32;
+
+    fn handle(&mut self, Ask: Ask) -> ControlFlow<i32, i32> {
+        match self.answer {
+            Some(answer) => ControlFlow::Continue(answer),
+            None => ControlFlow::Break(-1),
+        }
+    }
+
+    fn on_return(&mut self, ret: i32) -> i32 {
+        ret * 10
+    }
+
+    fn on_drop(&mut self) {
+        self.dropped.set(self.dropped.get() + 1);
+    }
+}
+
+/// Answers every `Ask` with `answer` once its future is polled, and counts how many times it has
+/// been finished with.
+struct AnswerLater<'a> {
+    answer: i32,
+    dropped: &'a Cell<u32>,
+}
+
+impl AsyncHandler<Ask, i32> for AnswerLater<'_> {
+    type Output = String;
+
+    async fn handle(&mut self, Ask: Ask) -> ControlFlow<String, i32> {
+        ControlFlow::Continue(self.answer)
+    }
+
+    fn on_return(&mut self, ret: i32) -> String {
+        ret.to_string()
+    }
+
+    fn on_drop(&mut self) {
+        self.dropped.set(self.dropped.get() + 1);
+    }
+}
+
+handler! {
+    struct Counter { value: i32 } for state::State<i32>,
+    get() => ControlFlow::Continue(self.value),
+    put(v) => {
+        self.value = v;
+        ControlFlow::Continue(())
+    },
+    return(ret) -> (R, i32) => (ret, self.value),
+}
+
+#[test]
+fn on_return_and_on_drop() {
+    let dropped = Cell::new(0);
+    let answer = Answer {
+        answer: Some(2),
+        dropped: &dropped,
+    };
+    let mut logs = Vec::new();
+    let logged = handle(handle(ask_and_log(), answer), |Log(msg)| {
+        logs.push(msg);
+        ControlFlow::Continue(())
+    });
+    assert_eq!(run(logged), 40);
+    assert_eq!(logs, ["asked 2"]);
+    assert_eq!(dropped.get(), 1);
+
+    let answer = Answer {
+        answer: None,
+        dropped: &dropped,
+    };
+    let logged = handle(handle(ask_and_log(), answer), |Log(_)| {
+        ControlFlow::Continue(())
+    });
+    assert_eq!(run(logged), -1);
+    assert_eq!(dropped.get(), 2);
+}
+
+#[test]
+fn on_drop_when_dropped_part_way() {
+    let dropped = Cell::new(0);
+    let answer = Answer {
+        answer: Some(2),
+        dropped: &dropped,
+    };
+    {
+        let mut asked = pin!(handle(ask_and_log(), answer));
+        // this stops at the `Log`, which nothing handles, and then drops the computation
+        let state = asked.as_mut().resume(Coproduct::inject(Begin));
+        assert!(matches!(
+            state,
+            GeneratorState::Yielded(Coproduct::Inl(Log(_)))
+        ));
+        assert_eq!(dropped.get(), 0);
+    }
+    assert_eq!(dropped.get(), 1);
+}
+
+#[test]
+fn on_drop_before_start() {
+    let dropped = Cell::new(0);
+    let answer = Answer {
+        answer: Some(2),
+        dropped: &dropped,
+    };
+    drop(handle(ask_and_log(), answer));
+    assert_eq!(dropped.get(), 1);
+}
+
+#[test]
+fn async_handler_hooks() {
+    let dropped = Cell::new(0);
+    let later = AnswerLater {
+        answer: 3,
+        dropped: &dropped,
+    };
+    assert_eq!(run_blocking(ask_sum(), later), "6");
+    assert_eq!(dropped.get(), 1);
+
+    let later = AnswerLater {
+        answer: 3,
+        dropped: &dropped,
+    };
+    drop(run_async(ask_sum(), later));
+    assert_eq!(dropped.get(), 2);
+}
+
+#[test]
+fn handler_struct_return_arm() {
+    let handled = handle(tick(), Counter { value: 3 });
+    assert_eq!(run(handled), (3, 4));
+}
@@ -0,0 +1,171 @@
+//! Effect groups declared with `effects!` or `#[effect]`, and the handlers `handler!` builds for
+//! them.
+
+#![cfg_attr(feature = "nightly", feature(generators))]
+#![cfg_attr(feature = "nightly", feature(generator_trait))]
+
+use core::ops::ControlFlow;
+
+use effing_mad::{
+    effect, effectful, handle, handle_group, handle_partial, handler, run, split_group,
+    std_effects::{run_state, state, State},
+    Handled,
+};
+
+#[effectful(State<i32>)]
+fn tick() -> i32 {
+    let n = yield_!(State::get());
+    yield_!(State::put(n + 1));
+    n
+}
+
+// the same as `tick`, but with `State`'s operations as separate effects
+#[effectful(state::Get<i32>, state::Put<i32>)]
+fn tick_ops() -> i32 {
+    let n = yield_!(State::get());
+    yield_!(State::put(n + 1));
+    n
+}
+
+effing_mad::effects! {
+    ring::Ring<const N: usize> {
+        fn step(from: usize) -> usize;
+        fn reset();
+    }
+}
+
+#[effectful(ring::Ring<3>)]
+fn walk() -> usize {
+    let a = yield_!(ring::Ring::step(2));
+    yield_!(ring::Ring::reset());
+    let b = yield_!(ring::Ring::step(a));
+    a + b
+}
+
+/// Keeps a running total.
+#[effect]
+trait Tally<T>
+where
+    T: Copy,
+{
+    /// Adds to the total and gives back the new total.
+    fn add(n: T) -> T;
+    fn total() -> T;
+    fn clear() {}
+}
+
+struct Total(i32);
+
+impl TallyHandler<i32> for Total {
+    fn add(&mut self, n: i32) -> i32 {
+        self.0 += n;
+        self.0
+    }
+    fn total(&mut self) -> i32 {
+        self.0
+    }
+}
+
+#[effectful(Tally<i32>)]
+fn tally() -> i32 {
+    yield_!(Tally::clear());
+    let first = yield_!(Tally::add(4));
+    yield_!(Tally::add(first * 2));
+    yield_!(Tally::total())
+}
+
+#[test]
+fn const_generic_effects() {
+    let mut resets = 0;
+    let handled = handle(
+        walk(),
+        handler! {
+            ring::Ring<3>,
+            step(from) => ControlFlow::Continue((from + 1) % 3),
+            reset() => {
+                resets += 1;
+                ControlFlow::Continue(())
+            },
+        },
+    );
+    assert_eq!(run(handled), 1);
+    assert_eq!(resets, 1);
+}
+
+#[test]
+fn partial_and_wildcard_handlers() {
+    let handled = handle_partial(
+        tick(),
+        handler! {
+            state::State<i32>,
+            get() => ControlFlow::Continue(10),
+            ..
+        },
+    );
+    assert_eq!(run(run_state(handled, 3)), (10, 11));
+
+    let handled = handle(
+        tick(),
+        handler! {
+            state::State<i32>,
+            get() => ControlFlow::Continue(10),
+            _ => ControlFlow::Break(-1),
+        },
+    );
+    assert_eq!(run(handled), -1);
+}
+
+#[test]
+fn split_and_rejoin_groups() {
+    let mut put = None;
+    let handled = handle(split_group(tick()), |_: state::Get<i32>| {
+        ControlFlow::Continue(7)
+    });
+    let handled = handle(handled, |op: state::Put<i32>| {
+        put = Some(op.0);
+        ControlFlow::Continue(())
+    });
+    assert_eq!(run(handled), 7);
+    assert_eq!(put, Some(8));
+
+    let mut state = 3;
+    let handled = handle_group(
+        tick_ops(),
+        handler! {
+            state::State<i32>,
+            get() => ControlFlow::Continue(state),
+            put(v) => {
+                state = v;
+                ControlFlow::Continue(())
+            },
+        },
+    );
+    assert_eq!(run(handled), 3);
+    assert_eq!(state, 4);
+
+    let handled = handle_group(
+        split_group(tick()),
+        handler! {
+            state::State<i32>,
+            get() => ControlFlow::Continue(5),
+            _ => ControlFlow::Break(-1),
+        },
+    );
+    assert_eq!(run(handled), -1);
+}
+
+#[test]
+fn effect_traits() {
+    assert_eq!(run(handle(tally(), Handled(Total(1)))), 15);
+
+    let handled = handle(
+        tally(),
+        handler! {
+            tally::Tally<i32>,
+            add(n) => ControlFlow::Continue(n),
+            total() => ControlFlow::Continue(0),
+            clear() => ControlFlow::Break(-1),
+        },
+    );
+    assert_eq!(run(handled), -1);
+}
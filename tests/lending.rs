@@ -0,0 +1,119 @@
+//! Effects that borrow from the function yielding them, and injections that borrow from the handler
+//! giving them. Both hand out pointers into pinned generator frames behind erased lifetimes, so
+//! these tests are also meant to be run under Miri:
+//!
+//! ```sh
+//! cargo miri test --test lending
+//! cargo miri test --test lending --no-default-features
+//! ```
+
+#![cfg_attr(feature = "nightly", feature(generators))]
+#![cfg_attr(feature = "nightly", feature(generator_trait))]
+#![cfg_attr(feature = "nightly", feature(generic_associated_types))]
+
+use core::ops::ControlFlow;
+
+use effing_mad::{
+    effectful, handle, handle_lending, run, Borrowed, Effect, Lend, LendInjection, Lent,
+};
+
+struct Ask;
+
+impl Effect for Ask {
+    type Injection = i32;
+}
+
+struct Log(String);
+
+impl Effect for Log {
+    type Injection = ();
+}
+
+struct Say<'a>(&'a str);
+
+impl<'a> Effect for Say<'a> {
+    type Injection = usize;
+}
+
+impl<'a> Lend for Say<'a> {
+    type At<'b> = Say<'b>;
+}
+
+struct Scratch;
+
+impl Effect for Scratch {
+    type Injection = Borrowed<Self>;
+}
+
+impl LendInjection for Scratch {
+    type At<'h> = &'h mut Vec<i32>;
+}
+
+#[effectful(Ask)]
+fn ask_twice() -> i32 {
+    let a = yield_!(Ask);
+    a + yield_!(Ask)
+}
+
+#[effectful(Lent<Say<'static>>)]
+fn say_lines() -> usize {
+    let lines = vec![String::from("one"), String::from("three")];
+    let mut said = 0;
+    for line in &lines {
+        said += yield_!(Say(line));
+    }
+    said
+}
+
+#[effectful(Lent<Say<'static>>, Log)]
+fn say_and_log() -> usize {
+    let said = say_lines().do_;
+    let greeting = format!("hello {said}");
+    yield_!(Log(greeting.clone()));
+    yield_!(Say(&greeting[..5]))
+}
+
+#[effectful(Scratch, Ask)]
+fn push_asked() -> usize {
+    let n = yield_!(Ask);
+    yield_!(Scratch).with(|scratch| scratch.push(n));
+    ask_twice().do_;
+    yield_!(Scratch).with(|scratch| scratch.len())
+}
+
+#[test]
+fn lend_borrowed_effects() {
+    let mut said = Vec::new();
+    let handled = handle(say_lines(), |say: Lent<Say<'static>>| {
+        ControlFlow::Continue(say.with(|Say(line)| {
+            said.push(line.to_owned());
+            line.len()
+        }))
+    });
+    assert_eq!(run(handled), 8);
+    assert_eq!(said, ["one", "three"]);
+
+    let said = handle(say_and_log(), |say: Lent<Say<'static>>| {
+        ControlFlow::Continue(say.with(|Say(line)| line.len()))
+    });
+    let mut logged = Vec::new();
+    let handled = handle(said, |Log(line)| {
+        logged.push(line);
+        ControlFlow::Continue(())
+    });
+    assert_eq!(run(handled), 5);
+    assert_eq!(logged, ["hello 8"]);
+}
+
+#[test]
+fn lend_from_handlers() {
+    let mut scratch = vec![1, 2];
+    let lent = handle_lending(
+        push_asked(),
+        Scratch::lend_from(&mut scratch, |scratch, Scratch| {
+            ControlFlow::Continue(&mut **scratch)
+        }),
+    );
+    assert_eq!(run(handle(lent, |Ask| ControlFlow::Continue(3))), 3);
+    assert_eq!(scratch, [1, 2, 3]);
+}
@@ -0,0 +1,74 @@
+//! Multi-shot handlers, which resume copies of the same suspended computation more than once.
+//! These need cloneable generators, which are only available with the `nightly` feature.
+
+#![cfg(feature = "nightly")]
+#![feature(generators)]
+#![feature(generator_trait)]
+#![feature(generator_clone)]
+
+use effing_mad::{
+    effectful, handle_multishot, handle_multishot_with_return,
+    std_effects::{run_choose, Choose},
+    Effect,
+};
+
+struct Pick(u32);
+
+impl Effect for Pick {
+    /// A number below the one in the effect.
+    type Injection = u32;
+}
+
+#[effectful(Choose; Clone)]
+fn flip_three() -> (bool, bool, bool) {
+    let a = yield_!(Choose);
+    let b = yield_!(Choose);
+    let c = yield_!(Choose);
+    (a, b, c)
+}
+
+#[effectful(Pick; Clone)]
+fn pick_two() -> u32 {
+    let tens = yield_!(Pick(3));
+    let units = yield_!(Pick(tens + 1));
+    tens * 10 + units
+}
+
+#[test]
+fn choose_every_branch() {
+    let branches: Vec<_> = run_choose(flip_three());
+    assert_eq!(
+        branches,
+        [
+            (true, true, true),
+            (true, true, false),
+            (true, false, true),
+            (true, false, false),
+            (false, true, true),
+            (false, true, false),
+            (false, false, true),
+            (false, false, false),
+        ]
+    );
+}
+
+#[test]
+fn resume_continuations_many_times() {
+    // every continuation is resumed once for each number below the one asked for
+    let sum = handle_multishot(pick_two(), |Pick(below), k| {
+        (0..below).map(|n| k.resume(n)).sum()
+    });
+    // 0, 10 and 11, then 20, 21 and 22
+    assert_eq!(sum, 10 + 11 + 20 + 21 + 22);
+
+    let picked = handle_multishot_with_return(
+        pick_two(),
+        |Pick(below), k| (0..below).flat_map(|n| k.resume(n)).collect(),
+        |ret| vec![ret],
+    );
+    assert_eq!(picked, [0, 10, 11, 20, 21, 22]);
+
+    // not resuming at all ends every branch there
+    let none = handle_multishot_with_return(pick_two(), |Pick(_), _| Vec::new(), |ret| vec![ret]);
+    assert!(none.is_empty());
+}
@@ -0,0 +1,451 @@
+//! Effectful functions that use `do_` pin their callees to their own stack, so they are
+//! self-referential. These tests put such functions through every combinator, and are meant to be
+//! run under Miri to check that the combinators keep them pinned:
+//!
+//! ```sh
+//! cargo miri test --test pinning --features stream
+//! cargo miri test --test pinning --no-default-features --features stream
+//! ```
+
+#![cfg_attr(feature = "nightly", feature(generators))]
+#![cfg_attr(feature = "nightly", feature(generator_trait))]
+#![cfg_attr(feature = "nightly", feature(pin_macro))]
+
+use core::{
+    future::Future,
+    ops::ControlFlow,
+    pin::{pin, Pin},
+    task::{Context, Poll, Waker},
+};
+
+use effing_mad::{
+    effectful, effectful_expr,
+    frunk::{hlist, Coprod, Coproduct},
+    functor_eff::{IntoEffectful, IntoEffectfulIter},
+    generator::{Generator, GeneratorState},
+    handle, handle_many, handle_with_return,
+    injection::{Begin, Tagged},
+    map, run, run_async, run_async_many, run_blocking,
+    std_effects::{run_state, State},
+    transform0, Effect,
+};
+
+struct Ask;
+
+impl Effect for Ask {
+    type Injection = i32;
+}
+
+struct Log(String);
+
+impl Effect for Log {
+    type Injection = ();
+}
+
+#[effectful(Ask)]
+fn inner() -> i32 {
+    let a = yield_!(Ask);
+    a + yield_!(Ask)
+}
+
+#[effectful(Ask, Log)]
+fn outer() -> i32 {
+    let a = inner().do_;
+    yield_!(Log(format!("first: {a}")));
+    let b = inner().do_;
+    yield_!(Log(format!("second: {b}")));
+    a * b
+}
+
+#[effectful(Log)]
+fn log_lines() -> usize {
+    let lines = vec![String::from("one"), String::from("three")];
+    // held across both yields, which needs a generator that can borrow from itself
+    let longest = lines.iter().max_by_key(|line| line.len()).unwrap();
+    for line in &lines {
+        yield_!(Log(line.clone()));
+    }
+    longest.len()
+}
+
+#[effectful(Ask, Log)]
+fn add(x: i32) -> i32 {
+    x + outer().do_
+}
+
+#[effectful(Ask, Log)]
+fn is_even(x: i32) -> bool {
+    let sum = add(x).do_;
+    sum % 2 == 0
+}
+
+#[effectful(Ask, Log)]
+fn checked_add(acc: i32, x: i32) -> Result<i32, i32> {
+    let sum = acc + add(x).do_;
+    if sum > 20 {
+        Err(sum)
+    } else {
+        Ok(sum)
+    }
+}
+
+#[effectful(Log)]
+fn log_twice(msg: &'static str) {
+    yield_!(Log(msg.into()));
+    yield_!(Log(msg.into()));
+}
+
+#[effectful(Log)]
+fn answer() -> i32 {
+    log_twice("asked").do_;
+    3
+}
+
+// logs around a computation, whatever other effects it has
+#[effectful(Log, ..E)]
+fn logged<E, G>(g: G) -> i32
+where
+    G: Generator<E::List, Yield = E, Return = i32>,
+{
+    yield_!(Log("before".into()));
+    let ret = g.do_;
+    log_twice("after").do_;
+    ret
+}
+
+#[effectful(Log, Ask, ..E)]
+fn logged_and_added<E, G>(g: G) -> i32
+where
+    G: Generator<E::List, Yield = E, Return = i32>,
+{
+    let ret = logged(g).do_;
+    ret + yield_!(Ask)
+}
+
+#[effectful(State<i32>)]
+fn tick() -> i32 {
+    let n = yield_!(State::get());
+    yield_!(State::put(n + 1));
+    n
+}
+
+fn ask<R>(n: i32) -> impl FnMut(Ask) -> ControlFlow<R, i32> {
+    move |Ask| ControlFlow::Continue(n)
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(ret) = future.as_mut().poll(&mut cx) {
+            return ret;
+        }
+    }
+}
+
+#[test]
+fn handle_and_run() {
+    let mut logs = Vec::new();
+    let asked = handle(outer(), ask(2));
+    let logged = handle(asked, |Log(msg)| {
+        logs.push(msg);
+        ControlFlow::Continue(())
+    });
+    assert_eq!(run(logged), 16);
+    assert_eq!(logs, ["first: 4", "second: 4"]);
+}
+
+#[test]
+fn map_return() {
+    let mapped = map(outer(), |ret| ret.to_string());
+    let asked = handle(mapped, ask(1));
+    let logged = handle(asked, |Log(_)| ControlFlow::Continue(()));
+    assert_eq!(run(logged), "4");
+}
+
+#[test]
+fn handle_with_return_clause() {
+    let asked = handle_with_return(outer(), ask(3), |ret| ret + 1);
+    let logged = handle(asked, |Log(_)| ControlFlow::Continue(()));
+    assert_eq!(run(logged), 37);
+}
+
+#[test]
+fn break_out_part_way() {
+    let asked = handle(outer(), ask(1));
+    let logged = handle(asked, |Log(msg)| ControlFlow::Break(msg.len() as i32));
+    assert_eq!(run(logged), 8);
+}
+
+#[test]
+fn handle_many_effects() {
+    let handled = handle_many(
+        outer(),
+        Vec::new(),
+        hlist![
+            |_: &mut Vec<String>, Ask| ControlFlow::Continue(5),
+            |logs: &mut Vec<String>, Log(msg)| {
+                logs.push(msg);
+                ControlFlow::Continue(())
+            },
+        ],
+    );
+    assert_eq!(run(handled), 100);
+}
+
+#[test]
+fn transform_with_effectful_handler() {
+    let mut logs = 0;
+    let transformed = transform0(outer(), |Ask| answer());
+    let logged = handle(transformed, |Log(_)| {
+        logs += 1;
+        ControlFlow::Continue(())
+    });
+    assert_eq!(run(logged), 36);
+    // two for each of the four `Ask`s, then the two from `outer`
+    assert_eq!(logs, 10);
+}
+
+#[test]
+fn run_async_effect() {
+    let logged = handle(outer(), |Log(_)| ControlFlow::Continue(()));
+    let ret = block_on(run_async(logged, |Ask| async { ControlFlow::Continue(4) }));
+    assert_eq!(ret, 64);
+}
+
+#[test]
+fn run_blocking_effect() {
+    // a future that isn't ready the first time it's polled, and wakes itself up for the next
+    struct Later(bool);
+
+    impl Future for Later {
+        type Output = ControlFlow<i32, i32>;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            if self.0 {
+                Poll::Ready(ControlFlow::Continue(4))
+            } else {
+                self.0 = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    let logged = handle(outer(), |Log(_)| ControlFlow::Continue(()));
+    assert_eq!(run_blocking(logged, |Ask| Later(false)), 64);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn run_blocking_woken_elsewhere() {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    // a future that's made ready, and woken, by another thread
+    struct Elsewhere {
+        ready: Arc<AtomicBool>,
+        started: bool,
+    }
+
+    impl Future for Elsewhere {
+        type Output = ControlFlow<i32, i32>;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            if self.ready.load(Ordering::Acquire) {
+                return Poll::Ready(ControlFlow::Continue(4));
+            }
+            if !self.started {
+                self.started = true;
+                let ready = Arc::clone(&self.ready);
+                let waker = cx.waker().clone();
+                thread::spawn(move || {
+                    thread::sleep(Duration::from_millis(10));
+                    ready.store(true, Ordering::Release);
+                    waker.wake();
+                });
+            }
+            Poll::Pending
+        }
+    }
+
+    let logged = handle(outer(), |Log(_)| ControlFlow::Continue(()));
+    let asked = run_blocking(logged, |Ask| Elsewhere {
+        ready: Arc::new(AtomicBool::new(false)),
+        started: false,
+    });
+    assert_eq!(asked, 64);
+}
+
+#[test]
+fn run_async_many_effects() {
+    let mut logs = Vec::new();
+    let handlers = hlist![
+        |Log(msg)| {
+            logs.push(msg);
+            ControlFlow::Continue(())
+        },
+        |Ask| async { ControlFlow::Continue(3) },
+    ];
+    assert_eq!(block_on(run_async_many(outer(), handlers)), 36);
+    assert_eq!(logs, ["first: 6", "second: 6"]);
+
+    let handlers = hlist![|Ask| ControlFlow::Continue(1), |Log(msg)| async move {
+        ControlFlow::Break(msg.len() as i32)
+    },];
+    assert_eq!(block_on(run_async_many(outer(), handlers)), 8);
+}
+
+#[test]
+fn open_effect_rows() {
+    let mut logs = Vec::new();
+    let asked = handle(logged(inner()), ask(2));
+    let logged = handle(asked, |Log(msg)| {
+        logs.push(msg);
+        ControlFlow::Continue(())
+    });
+    assert_eq!(run(logged), 4);
+    assert_eq!(logs, ["before", "after", "after"]);
+
+    let stated = run_state(logged_and_added(tick()), 3);
+    let logged = handle(stated, |Log(_)| ControlFlow::Continue(()));
+    assert_eq!(run(handle(logged, ask(4))), (7, 4));
+}
+
+#[test]
+fn functor_eff_maps() {
+    let option = Some(1).into_effectful().map(add);
+    let asked = handle(option, ask(1));
+    let logged = handle(asked, |Log(_)| ControlFlow::Continue(()));
+    assert_eq!(run(logged), Some(5));
+
+    let result = Ok::<_, ()>(2).into_effectful().map(add);
+    let asked = handle(result, ask(1));
+    let logged = handle(asked, |Log(_)| ControlFlow::Continue(()));
+    assert_eq!(run(logged), Ok(6));
+
+    let result = Err::<(), _>(3).into_effectful().map_err(add);
+    let asked = handle(result, ask(1));
+    let logged = handle(asked, |Log(_)| ControlFlow::Continue(()));
+    assert_eq!(run(logged), Err(7));
+}
+
+#[test]
+fn borrow_locals_across_yields() {
+    let mut logged = Vec::new();
+    let handled = handle(log_lines(), |Log(line)| {
+        logged.push(line);
+        ControlFlow::Continue(())
+    });
+    assert_eq!(run(handled), 5);
+    assert_eq!(logged, ["one", "three"]);
+
+    let counted = effectful_expr!(Log =>
+        let lines = [1, 22, 333];
+        let mut total = 0;
+        for line in &lines {
+            yield_!(Log(line.to_string()));
+            total += line;
+        }
+        total
+    );
+    let handled = handle(counted, |Log(_)| ControlFlow::Continue(()));
+    assert_eq!(run(handled), 356);
+}
+
+#[test]
+fn iterator_adapters() {
+    fn run_iter<R>(
+        g: impl Generator<
+            Coprod!(Tagged<(), Log>, Tagged<i32, Ask>, Begin),
+            Yield = Coprod!(Log, Ask),
+            Return = R,
+        >,
+    ) -> R {
+        let asked = handle(g, ask(1));
+        run(handle(asked, |Log(_)| ControlFlow::Continue(())))
+    }
+
+    let mapped = (1..=3).into_effectful().map(add);
+    assert_eq!(run_iter(mapped.collect::<Vec<_>>()), [5, 6, 7]);
+
+    let filtered = (1..=4).into_effectful().filter(|x: &i32| is_even(*x));
+    assert_eq!(run_iter(filtered.collect::<Vec<_>>()), [2, 4]);
+
+    let mut seen = Vec::new();
+    let logged = (1..=2).into_effectful().map(add).for_each(|x| {
+        seen.push(x);
+        map(add(0), drop)
+    });
+    run_iter(logged);
+    assert_eq!(seen, [5, 6]);
+
+    assert_eq!(
+        run_iter((1..=3).into_effectful().fold(0, |acc, x| add(acc + x))),
+        18
+    );
+    assert_eq!(
+        run_iter((1..=3).into_effectful().try_fold(0, checked_add)),
+        Ok(18)
+    );
+    assert_eq!(
+        run_iter((1..=5).into_effectful().try_fold(0, checked_add)),
+        Err(26)
+    );
+    assert_eq!(
+        run_iter((1..=5).into_effectful().find(|x: &i32| is_even(*x + 1))),
+        Some(1)
+    );
+    assert!(run_iter((1..=5).into_effectful().any(is_even)));
+    assert!(!run_iter((1..=5).into_effectful().all(is_even)));
+}
+
+#[cfg(feature = "stream")]
+#[test]
+fn stream_of_effects() {
+    use effing_mad::stream::into_stream;
+    use futures::StreamExt;
+
+    let mut logs = Vec::new();
+    let mut effects = into_stream(outer());
+    block_on(async {
+        while let Some((effs, responder)) = effects.next().await {
+            match effs {
+                Coproduct::Inl(Log(msg)) => {
+                    logs.push(msg);
+                    // answering the wrong effect gives the responder back
+                    let wrong = responder.respond::<Ask, _>(3).unwrap_err();
+                    assert_eq!(wrong.injection, 3);
+                    wrong.responder.respond::<Log, _>(()).unwrap();
+                }
+                Coproduct::Inr(Coproduct::Inl(Ask)) => responder.respond::<Ask, _>(2).unwrap(),
+                Coproduct::Inr(Coproduct::Inr(never)) => match never {},
+            }
+        }
+    });
+    assert_eq!(effects.into_return(), Some(16));
+    assert_eq!(logs, ["first: 4", "second: 4"]);
+
+    // dropping a responder abandons the computation
+    let mut effects = into_stream(outer());
+    block_on(async {
+        let (_, responder) = effects.next().await.unwrap();
+        drop(responder);
+        assert!(effects.next().await.is_none());
+    });
+    assert_eq!(effects.into_return(), None);
+}
+
+#[test]
+fn drop_part_way() {
+    let mut logged = pin!(handle(outer(), |Log(_)| ControlFlow::Continue(())));
+    // this stops inside `inner`, which `outer` has pinned to its stack, and then drops it all
+    let state = logged.as_mut().resume(Coproduct::inject(Begin));
+    assert!(matches!(state, GeneratorState::Yielded(_)));
+}
@@ -0,0 +1,191 @@
+//! The effects in `effing_mad::std_effects`, run with the handlers that come with them.
+
+#![cfg_attr(feature = "nightly", feature(generators))]
+#![cfg_attr(feature = "nightly", feature(generator_trait))]
+#![cfg_attr(feature = "nightly", feature(pin_macro))]
+
+use core::ops::ControlFlow;
+
+use effing_mad::{
+    effectful, handle, run,
+    std_effects::{
+        catch, run_cancel, run_exception, run_fresh, run_random, run_reader, run_state, run_writer,
+        Cancel, Exception, Fresh, Random, Reader, State, Throw, Writer,
+    },
+    Effect,
+};
+
+struct Log(String);
+
+impl Effect for Log {
+    type Injection = ();
+}
+
+#[effectful(State<i32>, Log)]
+fn count() -> i32 {
+    yield_!(Log("counting".into()));
+    let n = yield_!(State::get());
+    yield_!(State::put(n + 1));
+    n
+}
+
+#[effectful(Reader<i32>, Writer<&'static str>)]
+fn greet() -> i32 {
+    yield_!(Writer::tell("hello"));
+    let n = yield_!(Reader::ask());
+    yield_!(Writer::tell(" world"));
+    n + yield_!(Reader::ask())
+}
+
+#[effectful(Throw<&'static str>)]
+fn halve(n: i32) -> i32 {
+    if n % 2 != 0 {
+        match yield_!(Throw("odd")) {}
+    }
+    n / 2
+}
+
+#[effectful(Exception<&'static str>)]
+fn checked_sqrt(n: i32) -> i32 {
+    if n < 0 {
+        match yield_!(Throw("negative")) {}
+    }
+    (n as f64).sqrt() as i32
+}
+
+#[effectful(Cancel)]
+fn first_positive(ns: &[i32]) -> i32 {
+    match ns.iter().find(|&&n| n > 0) {
+        Some(&n) => n,
+        None => match yield_!(Cancel) {},
+    }
+}
+
+#[effectful(Fresh)]
+fn three_ids() -> [u64; 3] {
+    [yield_!(Fresh), yield_!(Fresh), yield_!(Fresh)]
+}
+
+#[effectful(Random)]
+fn roll_twice() -> (u64, u64) {
+    (yield_!(Random), yield_!(Random))
+}
+
+#[cfg(feature = "alloc")]
+fn block_on<F: core::future::Future>(future: F) -> F::Output {
+    use core::task::{Context, Poll, Waker};
+
+    let mut future = core::pin::pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(ret) = future.as_mut().poll(&mut cx) {
+            return ret;
+        }
+    }
+}
+
+#[test]
+fn state() {
+    let mut logs = Vec::new();
+    let stated = run_state(count(), 41);
+    let logged = handle(stated, |Log(msg)| {
+        logs.push(msg);
+        ControlFlow::Continue(())
+    });
+    assert_eq!(run(logged), (41, 42));
+    assert_eq!(logs, ["counting"]);
+}
+
+#[test]
+fn reader_and_writer() {
+    let read = run_reader(greet(), 4);
+    let (ret, written): (_, String) = run(run_writer(read));
+    assert_eq!(ret, 8);
+    assert_eq!(written, "hello world");
+
+    let (ret, written): (_, Vec<_>) = run(run_reader(run_writer(greet()), 1));
+    assert_eq!(ret, 2);
+    assert_eq!(written, ["hello", " world"]);
+}
+
+#[test]
+fn throw_and_catch() {
+    assert_eq!(run(catch(halve(4))), Ok(2));
+    assert_eq!(run(catch(halve(5))), Err("odd"));
+    assert_eq!(run(run_exception(checked_sqrt(9))), Ok(3));
+    assert_eq!(run(run_exception(checked_sqrt(-9))), Err("negative"));
+}
+
+#[test]
+fn cancel() {
+    assert_eq!(run(run_cancel(first_positive(&[-1, 3, 5]))), Some(3));
+    assert_eq!(run(run_cancel(first_positive(&[-1, 0]))), None);
+}
+
+#[test]
+fn fresh() {
+    assert_eq!(run(run_fresh(three_ids())), [0, 1, 2]);
+    // every run counts from 0 again
+    assert_eq!(run(run_fresh(three_ids())), [0, 1, 2]);
+}
+
+#[test]
+fn random() {
+    let (a, b) = run(run_random(roll_twice(), 7));
+    assert_ne!(a, b);
+    assert_eq!(run(run_random(roll_twice(), 7)), (a, b));
+    assert_ne!(run(run_random(roll_twice(), 8)), (a, b));
+
+    // a seed of 0 would only ever give 0
+    let (a, b) = run(run_random(roll_twice(), 0));
+    assert_ne!(a, 0);
+    assert_ne!(b, 0);
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn await_futures() {
+    use effing_mad::{
+        frunk::hlist,
+        run_async_many,
+        std_effects::{await_future, run_await, Await, LocalAwait},
+        transform1,
+    };
+
+    struct Ask;
+
+    impl Effect for Ask {
+        type Injection = i32;
+    }
+
+    #[effectful(Ask)]
+    fn ask_twice() -> i32 {
+        let a = yield_!(Ask);
+        a + yield_!(Ask)
+    }
+
+    #[effectful(Await<i32>)]
+    fn ask_later() -> i32 {
+        let a = yield_!(Await::new(async { 3 }));
+        a + yield_!(Await::new(async { 4 }))
+    }
+
+    #[effectful(LocalAwait<i32>)]
+    fn ask_shared() -> i32 {
+        // an `Rc` isn't `Send`, so neither is a future holding one
+        let shared = std::rc::Rc::new(5);
+        let held = std::rc::Rc::clone(&shared);
+        let a = yield_!(Await::local(async move { *held }));
+        a + std::rc::Rc::strong_count(&shared) as i32
+    }
+
+    assert_eq!(block_on(run_await(ask_later())), 7);
+    assert_eq!(block_on(run_await(ask_shared())), 6);
+
+    // the futures come from the handler, not the computation being handled
+    let transformed = transform1(ask_twice(), |Ask| ask_later());
+    assert_eq!(
+        block_on(run_async_many(transformed, hlist![await_future])),
+        14
+    );
+}
@@ -0,0 +1,12 @@
+//! Mistakes in using the macros should be reported as errors pointing at the mistake. These tests
+//! check the errors against the `.stderr` files next to them, which can be regenerated with
+//! `TRYBUILD=overwrite cargo test --test ui`.
+
+#[test]
+fn ui() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+    // a bare `yield` is feature gated before the macro ever sees it
+    #[cfg(feature = "nightly")]
+    t.compile_fail("tests/ui/nightly/*.rs");
+}
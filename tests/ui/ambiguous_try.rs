@@ -0,0 +1,11 @@
+use effing_mad::effectful;
+
+#[effectful(
+    effing_mad::std_effects::Throw<std::num::ParseIntError>,
+    effing_mad::std_effects::Throw<std::fmt::Error>,
+)]
+fn parse(s: &str) -> i32 {
+    s.parse()?
+}
+
+fn main() {}
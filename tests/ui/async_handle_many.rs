@@ -0,0 +1,14 @@
+use effing_mad::{handler, Effect};
+
+struct Log;
+
+impl Effect for Log {
+    type Injection = ();
+}
+
+fn main() {
+    let _ = handler! {
+        async
+        Log => core::ops::ControlFlow::<(), ()>::Continue(()),
+    };
+}
@@ -0,0 +1,19 @@
+use effing_mad::{effectful, Effect, Lend};
+
+struct Say<'a>(&'a str);
+
+impl<'a> Effect for Say<'a> {
+    type Injection = usize;
+}
+
+impl<'a> Lend for Say<'a> {
+    type At<'b> = Say<'b>;
+}
+
+#[effectful(effing_mad::Lent<Say<'static>>; Clone)]
+fn say_hello() -> usize {
+    let hello = String::from("hello");
+    yield_!(Say(&hello))
+}
+
+fn main() {}
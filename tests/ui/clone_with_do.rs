@@ -0,0 +1,15 @@
+use effing_mad::{effectful, Effect};
+
+struct Choose;
+
+impl Effect for Choose {
+    type Injection = bool;
+}
+
+// the function is rejected before its body is looked at, so `choose` doesn't need to exist
+#[effectful(Choose; Clone)]
+fn choose_twice() -> bool {
+    choose().do_ && choose().do_
+}
+
+fn main() {}
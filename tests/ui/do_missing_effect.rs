@@ -0,0 +1,30 @@
+#![cfg_attr(feature = "nightly", feature(generators))]
+#![cfg_attr(feature = "nightly", feature(generator_trait))]
+
+use effing_mad::{effectful, Effect};
+
+struct Ask;
+
+impl Effect for Ask {
+    type Injection = i32;
+}
+
+struct Log(String);
+
+impl Effect for Log {
+    type Injection = ();
+}
+
+#[effectful(Ask, Log)]
+fn ask_and_log() -> i32 {
+    yield_!(Log("asking".into()));
+    yield_!(Ask)
+}
+
+#[effectful(Ask)]
+fn only_ask() -> i32 {
+    let asked = ask_and_log().do_;
+    asked + 1
+}
+
+fn main() {}
@@ -0,0 +1,7 @@
+effing_mad::effects! {
+    http::Http {
+        fn get(url) -> String;
+    }
+}
+
+fn main() {}
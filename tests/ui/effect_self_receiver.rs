@@ -0,0 +1,7 @@
+#[effing_mad::effect]
+trait Console {
+    fn read_line() -> String;
+    fn print(&self, text: String);
+}
+
+fn main() {}
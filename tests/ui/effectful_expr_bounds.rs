@@ -0,0 +1,9 @@
+struct Ask;
+
+impl effing_mad::Effect for Ask {
+    type Injection = i32;
+}
+
+fn main() {
+    let _ = effing_mad::effectful_expr!(Ask; Clone => |x: i32| x + yield_!(Ask));
+}
@@ -0,0 +1,8 @@
+use effing_mad::effectful;
+
+#[effectful()]
+fn nothing() {
+    yield_!();
+}
+
+fn main() {}
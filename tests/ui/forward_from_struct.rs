@@ -0,0 +1,16 @@
+use effing_mad::handler;
+
+effing_mad::effects! {
+    state::State<T> {
+        fn get() -> T;
+        fn put(v: T);
+    }
+}
+
+handler! {
+    struct Reads { value: i32 } for state::State<i32>,
+    get() => core::ops::ControlFlow::Continue(self.value),
+    ..
+}
+
+fn main() {}
@@ -0,0 +1,8 @@
+use effing_mad::{handler, std_effects::state};
+
+fn main() {
+    let _ = handler! {
+        state::State<i32>,
+        get() => core::ops::ControlFlow::<(), _>::Continue(0),
+    };
+}
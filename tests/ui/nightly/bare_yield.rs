@@ -0,0 +1,10 @@
+#![feature(generators)]
+
+use effing_mad::effectful;
+
+#[effectful()]
+fn nothing() {
+    yield;
+}
+
+fn main() {}
@@ -0,0 +1,12 @@
+use effing_mad::{effectful, Effect};
+
+struct Log;
+
+impl Effect for Log {
+    type Injection = ();
+}
+
+#[effectful(..E, Log)]
+fn logged<E>() {}
+
+fn main() {}
@@ -0,0 +1,20 @@
+use effing_mad::handler;
+
+effing_mad::effects! {
+    state::State<T> {
+        fn get() -> T;
+        fn put(v: T);
+    }
+}
+
+handler! {
+    struct Counter { value: i32 } for state::State<i32>,
+    get() => core::ops::ControlFlow::Continue(self.value),
+    put(v) => {
+        self.value = v;
+        core::ops::ControlFlow::Continue(())
+    },
+    return(v) => v,
+}
+
+fn main() {}
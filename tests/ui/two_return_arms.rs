@@ -0,0 +1,18 @@
+use effing_mad::handler;
+
+effing_mad::effects! {
+    state::State<T> {
+        fn get() -> T;
+        fn put(v: T);
+    }
+}
+
+fn main() {
+    let _ = handler! {
+        state::State<i32>,
+        get() => core::ops::ControlFlow::Continue(0),
+        put(_) => core::ops::ControlFlow::Continue(()),
+        return(v) => v,
+        return(v) => v + 1,
+    };
+}
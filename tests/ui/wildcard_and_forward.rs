@@ -0,0 +1,17 @@
+use effing_mad::handler;
+
+effing_mad::effects! {
+    state::State<T> {
+        fn get() -> T;
+        fn put(v: T);
+    }
+}
+
+fn main() {
+    let _ = handler! {
+        state::State<i32>,
+        get() => core::ops::ControlFlow::<(), _>::Continue(0),
+        _ => core::ops::ControlFlow::Break(()),
+        ..
+    };
+}
@@ -0,0 +1,24 @@
+#![cfg_attr(feature = "nightly", feature(generators))]
+#![cfg_attr(feature = "nightly", feature(generator_trait))]
+
+use effing_mad::{effectful, Effect};
+
+struct Log(&'static str);
+
+impl Effect for Log {
+    type Injection = ();
+}
+
+#[effectful(Log)]
+fn logged() {}
+
+// none of these would suspend `leak`, and the async block could even be awaited by another
+// effectful function, so they are all rejected
+#[effectful(Log)]
+fn leak() {
+    let _later = async move { yield_!(Log("later")) };
+    let _each = |line| yield_!(Log(line));
+    let _called = || logged().do_;
+}
+
+fn main() {}